@@ -0,0 +1,39 @@
+use alloc_metrics::MetricAlloc;
+use axum::{Router, extract::Query, routing::get};
+use serde::Deserialize;
+
+// Add this two lines to have allocator statistics
+#[global_allocator]
+static GLOBAL: MetricAlloc<std::alloc::System> = MetricAlloc::new(std::alloc::System);
+
+#[tokio::main]
+pub async fn main() -> Result<(), std::io::Error> {
+    // The name is shown on the top of status page
+    let groundwork = groundwork::Groundwork::new("Hello axum");
+
+    groundwork.register_stdout_tracing_subscriber();
+
+    let api = Router::new()
+        .route("/hello", get(hello))
+        .layer(groundwork.calls_middleware()); // this is the way to trace only some calls
+
+    let app = groundwork
+        .register_axum_handlers(Router::new(), "/status")
+        .nest("/api", api);
+    // .layer(groundwork.calls_middleware()) // this line enable tracing for all calls
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+    axum::serve(listener, app).await
+}
+
+#[derive(Deserialize)]
+struct Hello {
+    name: Option<String>,
+}
+
+async fn hello(Query(query): Query<Hello>) -> String {
+    match query.name {
+        Some(name) => format!("hello, {name}!"),
+        None => "hello!".to_string(),
+    }
+}
@@ -1,6 +1,9 @@
 use alloc_metrics::MetricAlloc;
+use groundwork::call::QueryRule;
 use poem::{EndpointExt, Route, Server, listener::TcpListener, middleware::Tracing};
 use poem_openapi::{OpenApi, OpenApiService, param::Query, payload::PlainText};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
 // Add this two lines to have allocator statistics
 #[global_allocator]
@@ -9,26 +12,33 @@ static GLOBAL: MetricAlloc<std::alloc::System> = MetricAlloc::new(std::alloc::Sy
 #[tokio::main]
 pub async fn main() -> Result<(), std::io::Error> {
     // The name is shown on the top of status page
-    let groundwork = groundwork::DefaultGroundwork::new("Hello world");
+    let groundwork = groundwork::Groundwork::new("Hello world");
 
     // You can register default groundwork tracer to stdout
     // groundwork.register_stdout_tracing_subscriber();
 
-    // Or your own tracer with groundwork proxy
-    tracing_subscriber::fmt::Subscriber::builder()
-        .with_writer(groundwork.trace_writer(tracing_subscriber::fmt::TestWriter::new()))
-        .with_ansi(false)
+    // Or add groundwork layer to your own subscriber
+    tracing_subscriber::registry()
+        .with(groundwork.log_filter_layer("info").unwrap()) // allows to change log level at runtime
+        .with(tracing_subscriber::fmt::layer().with_ansi(false))
+        .with(groundwork.tracing_layer())
         .init();
 
-    log::set_max_level(log::LevelFilter::Trace);
-
     let api_service =
         OpenApiService::new(Api, "Hello World", "1.0").server("http://localhost:8080/api");
     let ui = api_service.swagger_ui();
 
     let route = groundwork
         .register_handlers(Route::new(), "/status")
-        .nest("/api", api_service.with(groundwork.calls_middleware())) // this is the way to trace only some calls
+        .nest(
+            "/api",
+            // this is the way to trace only some calls, values of `name` are not recorded
+            api_service.with(
+                groundwork
+                    .calls_middleware()
+                    .with_query_rule(QueryRule::Redact(vec!["name".to_string()])),
+            ),
+        )
         .nest("/", ui)
         // .with(groundwork.calls_middleware()) // this line enable tracing for all calls
         .with(Tracing);
@@ -45,7 +55,11 @@ impl Api {
     #[oai(path = "/hello", method = "get")]
     async fn index(&self, name: Query<Option<String>>) -> PlainText<String> {
         match name.0 {
-            Some(name) => PlainText(format!("hello, {name}!")),
+            Some(name) => {
+                // log crate records are shown on the status page too
+                log::info!("greeting {name}");
+                PlainText(format!("hello, {name}!"))
+            }
             None => PlainText("hello!".to_string()),
         }
     }
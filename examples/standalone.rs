@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+use alloc_metrics::MetricAlloc;
+
+// Add this two lines to have allocator statistics
+#[global_allocator]
+static GLOBAL: MetricAlloc<std::alloc::System> = MetricAlloc::new(std::alloc::System);
+
+pub fn main() -> Result<(), std::io::Error> {
+    // The name is shown on the top of status page, the settings are read from
+    // `GROUNDWORK_*` environment variables and the `GROUNDWORK_CONFIG` file
+    let config = groundwork::Config::from_env().expect("valid groundwork configuration");
+    let groundwork = groundwork::Groundwork::builder("Standalone worker")
+        .config(config)
+        .refresh_interval(Duration::from_secs(5))
+        .build();
+    groundwork.register_stdout_tracing_subscriber();
+
+    // The status page is served on its own port, no web framework is needed
+    let server = groundwork.serve("127.0.0.1:8081")?;
+    println!("status page: http://{}/", server.local_addr());
+
+    // Do the actual work here
+    std::thread::sleep(Duration::from_secs(60));
+
+    server.shutdown()
+}
@@ -0,0 +1,526 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+#[cfg(any(feature = "poem", feature = "axum"))]
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Query parameter accepted instead of the `Authorization` header by the live log stream only,
+/// the page uses it because `EventSource` can't set headers. Query strings end up in access
+/// logs and `Referer` headers, so other endpoints ignore it.
+pub const AUTHORIZATION_PARAM: &str = "authorization";
+
+/// Address or CIDR network, e.g. `10.0.0.0/8` or `::1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual stack listener are reported as mapped IPv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                mask(u32::from(network), self.prefix_len) == mask(u32::from(ip), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                mask(u128::from(network), self.prefix_len) == mask(u128::from(ip), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn mask<T>(bits: T, prefix_len: u8) -> T
+where
+    T: std::ops::Shr<u32, Output = T> + std::ops::Shl<u32, Output = T> + Default + Copy,
+{
+    let width = (std::mem::size_of::<T>() * 8) as u32;
+    let shift = width - u32::from(prefix_len);
+    if shift == width {
+        T::default()
+    } else {
+        (bits >> shift) << shift
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidConfig(format!("invalid IP range {s:?}"));
+        let (network, prefix_len) = match s.split_once('/') {
+            Some((network, len)) => (network, Some(len)),
+            None => (s, None),
+        };
+        let network: IpAddr = network.trim().parse().map_err(|_| invalid())?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.trim().parse().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BasicCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Access rules of the endpoints, everything is allowed when no rule is set.
+///
+/// Requests from addresses outside of `allow_ips` are forbidden, the page is served to
+/// the allowed addresses without credentials so it can ask for them. When tokens or
+/// credentials are set, the endpoints require one of them.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Accepted `Authorization: Bearer` tokens.
+    pub bearer_tokens: Vec<String>,
+    /// Accepted HTTP Basic credentials.
+    pub basic: Vec<BasicCredentials>,
+    pub allow_ips: Vec<IpRange>,
+}
+
+impl fmt::Debug for AuthConfig {
+    // the secrets are not printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("bearer_tokens", &self.bearer_tokens.len())
+            .field("basic", &self.basic.len())
+            .field("allow_ips", &self.allow_ips)
+            .finish()
+    }
+}
+
+/// Request passed to the predicate set with [`crate::GroundworkBuilder::auth_predicate`].
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub struct AuthRequest<'a> {
+    pub method: &'a http::Method,
+    pub uri: &'a http::Uri,
+    pub headers: &'a http::HeaderMap,
+    /// Remote address, if known to the framework.
+    pub client: Option<IpAddr>,
+}
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub(crate) type AuthPredicate = Arc<dyn Fn(&AuthRequest) -> bool + Send + Sync>;
+
+/// What a guard protects.
+#[cfg(any(feature = "poem", feature = "axum"))]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// The page, which needs no credentials.
+    Page,
+    /// An endpoint, credentials are taken from the `Authorization` header.
+    Endpoint,
+    /// The live log stream, credentials are also taken from the [`AUTHORIZATION_PARAM`].
+    Stream,
+}
+
+/// Why a request was rejected.
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub(crate) enum Denied {
+    /// Credentials are missing or wrong, the value is the `WWW-Authenticate` challenge.
+    Unauthorized(&'static str),
+    Forbidden,
+}
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+impl Denied {
+    pub(crate) fn status(&self) -> u16 {
+        match self {
+            Self::Unauthorized(_) => 401,
+            Self::Forbidden => 403,
+        }
+    }
+}
+
+/// [`AuthConfig`] prepared for checks, with the predicate.
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub(crate) struct Auth {
+    /// Accepted `Authorization` schemes and credentials.
+    authorizations: Vec<(&'static str, String)>,
+    challenge: &'static str,
+    allow_ips: Vec<IpRange>,
+    predicate: Option<AuthPredicate>,
+}
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+impl Auth {
+    pub(crate) fn new(config: &AuthConfig, predicate: Option<AuthPredicate>) -> Self {
+        use base64::Engine;
+        let bearer = config.bearer_tokens.iter().map(|t| ("Bearer", t.clone()));
+        let basic = config.basic.iter().map(|c| {
+            let encoded = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", c.username, c.password));
+            ("Basic", encoded)
+        });
+        Self {
+            authorizations: bearer.chain(basic).collect(),
+            // browsers show their login dialog for the Basic challenge
+            challenge: if config.basic.is_empty() {
+                "Bearer realm=\"groundwork\""
+            } else {
+                "Basic realm=\"groundwork\""
+            },
+            allow_ips: config.allow_ips.clone(),
+            predicate,
+        }
+    }
+
+    /// Checks a request, `authorization` is the header or the query parameter. The page needs
+    /// no credentials, the predicate decides for every request.
+    pub(crate) fn check(
+        &self,
+        access: Access,
+        request: &AuthRequest,
+        authorization: Option<&str>,
+    ) -> Result<(), Denied> {
+        self.check_client(request.client)?;
+        if access != Access::Page
+            && !self.authorizations.is_empty()
+            && !authorization.is_some_and(|value| self.is_accepted(value))
+        {
+            return Err(Denied::Unauthorized(self.challenge));
+        }
+        match &self.predicate {
+            Some(predicate) if !predicate(request) => Err(Denied::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// The scheme is case insensitive, the credentials are compared in constant time.
+    fn is_accepted(&self, authorization: &str) -> bool {
+        let Some((scheme, credentials)) = authorization.trim().split_once(' ') else {
+            return false;
+        };
+        let credentials = credentials.trim_start();
+        self.authorizations
+            .iter()
+            .any(|(expected_scheme, expected)| {
+                scheme.eq_ignore_ascii_case(expected_scheme)
+                    && constant_time_eq(expected.as_bytes(), credentials.as_bytes())
+            })
+    }
+
+    fn check_client(&self, client: Option<IpAddr>) -> Result<(), Denied> {
+        if self.allow_ips.is_empty()
+            || client.is_some_and(|ip| self.allow_ips.iter().any(|r| r.contains(ip)))
+        {
+            Ok(())
+        } else {
+            Err(Denied::Forbidden)
+        }
+    }
+}
+
+/// The `Authorization` header, or the query parameter of the stream if there is no header.
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub(crate) fn authorization<'a>(
+    access: Access,
+    headers: &'a http::HeaderMap,
+    query: Option<&'a str>,
+) -> Option<std::borrow::Cow<'a, str>> {
+    if let Some(value) = headers.get(http::header::AUTHORIZATION) {
+        return value.to_str().ok().map(std::borrow::Cow::Borrowed);
+    }
+    if access != Access::Stream {
+        return None;
+    }
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == AUTHORIZATION_PARAM)
+        .map(|(_, value)| percent_decode(value))
+}
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+fn percent_decode(value: &str) -> std::borrow::Cow<'_, str> {
+    if !value.contains(['%', '+']) {
+        return std::borrow::Cow::Borrowed(value);
+    }
+    let hex = |b: u8| (b as char).to_digit(16);
+    let mut bytes = value.bytes();
+    let mut decoded = Vec::with_capacity(value.len());
+    while let Some(b) = bytes.next() {
+        match b {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let mut rest = bytes.clone();
+                match (rest.next().and_then(hex), rest.next().and_then(hex)) {
+                    (Some(high), Some(low)) => {
+                        decoded.push((high * 16 + low) as u8);
+                        bytes = rest;
+                    }
+                    _ => decoded.push(b),
+                }
+            }
+            b => decoded.push(b),
+        }
+    }
+    std::borrow::Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Comparison which doesn't leak the position of the first difference.
+#[cfg(any(feature = "poem", feature = "axum"))]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ip_range_parsing() {
+        assert_eq!(range("10.1.2.3").to_string(), "10.1.2.3/32");
+        assert_eq!(range(" 10.0.0.0 / 8 ").to_string(), "10.0.0.0/8");
+        assert_eq!(range("::1").to_string(), "::1/128");
+        assert_eq!(range("fd00::/8").to_string(), "fd00::/8");
+        assert_eq!(range("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        for malformed in [
+            "",
+            "10.0.0",
+            "10.0.0.0/",
+            "10.0.0.0/33",
+            "10.0.0.0/-1",
+            "10.0.0.0/8/8",
+            "::/129",
+            "localhost",
+        ] {
+            assert!(malformed.parse::<IpRange>().is_err(), "{malformed:?}");
+        }
+    }
+
+    #[test]
+    fn ip_range_contains() {
+        assert!(range("10.0.0.0/8").contains(ip("10.255.0.1")));
+        assert!(!range("10.0.0.0/8").contains(ip("11.0.0.1")));
+        assert!(range("10.1.2.3/32").contains(ip("10.1.2.3")));
+        assert!(!range("10.1.2.3/32").contains(ip("10.1.2.4")));
+        assert!(range("0.0.0.0/0").contains(ip("192.168.1.1")));
+        assert!(!range("0.0.0.0/0").contains(ip("::1")));
+        assert!(range("::/0").contains(ip("2001:db8::1")));
+        assert!(range("::1/128").contains(ip("::1")));
+        assert!(!range("::1/128").contains(ip("::2")));
+        assert!(range("fd00::/8").contains(ip("fdff::1")));
+        // IPv4 client of a dual stack listener
+        assert!(range("127.0.0.0/8").contains(ip("::ffff:127.0.0.1")));
+    }
+
+    #[test]
+    fn mask_keeps_prefix_bits() {
+        assert_eq!(mask(0xffff_ffff_u32, 0), 0);
+        assert_eq!(mask(0xffff_ffff_u32, 8), 0xff00_0000);
+        assert_eq!(mask(0xffff_ffff_u32, 32), 0xffff_ffff);
+        assert_eq!(mask(u128::MAX, 0), 0);
+        assert_eq!(mask(u128::MAX, 1), 1 << 127);
+        assert_eq!(mask(u128::MAX, 128), u128::MAX);
+    }
+
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("Bearer%20abc"), "Bearer abc");
+        assert_eq!(percent_decode("Basic+YTpi%3D%3d"), "Basic YTpi==");
+        assert!(matches!(
+            percent_decode("plain"),
+            std::borrow::Cow::Borrowed("plain")
+        ));
+        // malformed escapes are kept
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%C3%A9"), "é");
+    }
+
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"secret", b""));
+    }
+
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    mod check {
+        use super::*;
+
+        fn auth(config: AuthConfig) -> Auth {
+            Auth::new(&config, None)
+        }
+
+        fn check_access(
+            auth: &Auth,
+            access: Access,
+            client: Option<&str>,
+            authorization: Option<&str>,
+        ) -> Option<u16> {
+            let uri = http::Uri::from_static("/groundwork/stats");
+            let request = AuthRequest {
+                method: &http::Method::GET,
+                uri: &uri,
+                headers: &http::HeaderMap::new(),
+                client: client.map(ip),
+            };
+            auth.check(access, &request, authorization)
+                .err()
+                .map(|d| d.status())
+        }
+
+        fn check(auth: &Auth, client: Option<&str>, authorization: Option<&str>) -> Option<u16> {
+            check_access(auth, Access::Endpoint, client, authorization)
+        }
+
+        fn check_page(auth: &Auth, client: Option<&str>) -> Option<u16> {
+            check_access(auth, Access::Page, client, None)
+        }
+
+        #[test]
+        fn everything_is_allowed_without_rules() {
+            let auth = auth(AuthConfig::default());
+            assert_eq!(check(&auth, None, None), None);
+            assert_eq!(check_page(&auth, None), None);
+        }
+
+        #[test]
+        fn bearer_token() {
+            let auth = auth(AuthConfig {
+                bearer_tokens: vec!["t0ken".to_string()],
+                ..Default::default()
+            });
+            assert_eq!(check(&auth, None, Some("Bearer t0ken")), None);
+            assert_eq!(check(&auth, None, Some(" Bearer t0ken ")), None);
+            // the scheme is case insensitive, the token is not
+            assert_eq!(check(&auth, None, Some("bearer t0ken")), None);
+            assert_eq!(check(&auth, None, Some("BEARER  t0ken")), None);
+            assert_eq!(check(&auth, None, Some("Bearer T0KEN")), Some(401));
+            assert_eq!(check(&auth, None, Some("Basic t0ken")), Some(401));
+            assert_eq!(check(&auth, None, Some("Bearer other")), Some(401));
+            assert_eq!(check(&auth, None, Some("t0ken")), Some(401));
+            assert_eq!(check(&auth, None, None), Some(401));
+        }
+
+        #[test]
+        fn basic_credentials() {
+            let auth = auth(AuthConfig {
+                basic: vec![BasicCredentials {
+                    username: "admin".to_string(),
+                    password: "secret".to_string(),
+                }],
+                ..Default::default()
+            });
+            // base64 of `admin:secret`
+            assert_eq!(check(&auth, None, Some("Basic YWRtaW46c2VjcmV0")), None);
+            assert_eq!(check(&auth, None, Some("basic YWRtaW46c2VjcmV0")), None);
+            // base64 of `admin:wrong`
+            assert_eq!(
+                check(&auth, None, Some("Basic YWRtaW46d3Jvbmc=")),
+                Some(401)
+            );
+            assert_eq!(check(&auth, None, None), Some(401));
+        }
+
+        #[test]
+        fn allowed_ips() {
+            let auth = auth(AuthConfig {
+                bearer_tokens: vec!["t0ken".to_string()],
+                allow_ips: vec![range("10.0.0.0/8"), range("::1")],
+                ..Default::default()
+            });
+            assert_eq!(check(&auth, Some("10.0.0.1"), Some("Bearer t0ken")), None);
+            assert_eq!(check(&auth, Some("::1"), Some("Bearer t0ken")), None);
+            assert_eq!(check(&auth, Some("10.0.0.1"), None), Some(401));
+            assert_eq!(
+                check(&auth, Some("192.168.0.1"), Some("Bearer t0ken")),
+                Some(403)
+            );
+            // the address is unknown
+            assert_eq!(check(&auth, None, Some("Bearer t0ken")), Some(403));
+            assert_eq!(check_page(&auth, Some("10.0.0.1")), None);
+            assert_eq!(check_page(&auth, Some("192.168.0.1")), Some(403));
+        }
+
+        #[test]
+        fn predicate_decides_for_the_page_too() {
+            let predicate: AuthPredicate =
+                Arc::new(|request: &AuthRequest| request.headers.contains_key("x-internal"));
+            let auth = Auth::new(&AuthConfig::default(), Some(predicate));
+            assert_eq!(check(&auth, None, None), Some(403));
+            assert_eq!(check_page(&auth, None), Some(403));
+        }
+
+        #[test]
+        fn query_parameter_is_read_for_the_stream_only() {
+            let headers = http::HeaderMap::new();
+            let query = Some("level=info&authorization=Bearer%20t0ken");
+            assert_eq!(
+                authorization(Access::Stream, &headers, query).as_deref(),
+                Some("Bearer t0ken")
+            );
+            assert_eq!(authorization(Access::Endpoint, &headers, query), None);
+
+            let mut headers = http::HeaderMap::new();
+            headers.insert(
+                http::header::AUTHORIZATION,
+                http::HeaderValue::from_static("Bearer header"),
+            );
+            assert_eq!(
+                authorization(Access::Endpoint, &headers, query).as_deref(),
+                Some("Bearer header")
+            );
+            assert_eq!(
+                authorization(Access::Stream, &headers, query).as_deref(),
+                Some("Bearer header")
+            );
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use ::axum::{
+    Json, Router,
+    extract::{ConnectInfo, OriginalUri, Query, Request},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{
+        Html, IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::get,
+};
+use futures_util::StreamExt;
+use serde::Serialize;
+
+use crate::auth::{self, Access, Auth, AuthRequest, Denied};
+use crate::trace::{LogFilter, LogLevel};
+use crate::{Error, Groundwork, Section, W3_CSS, metrics};
+
+impl Groundwork {
+    /// Axum version of [`Groundwork::register_handlers`], to record calls
+    /// add [`Groundwork::calls_middleware`] as a layer.
+    pub fn register_axum_handlers<S>(&self, router: Router<S>, page_path: &str) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        self.start_sampling();
+        let gw = self.clone();
+        let stats = move || {
+            let response = json(gw.stats());
+            async move { response }
+        };
+        let gw = self.clone();
+        let stats_history = move || {
+            let response = Json(gw.stats_history()).into_response();
+            async move { response }
+        };
+        let gw = self.clone();
+        let logs = move |Query(filter): Query<LogFilter>| {
+            let response = json(gw.logs(&filter));
+            async move { response }
+        };
+        let gw = self.clone();
+        let logs_stream = move |Query(filter): Query<LogFilter>| {
+            let response = match gw.logs_stream(&filter) {
+                Ok(stream) => Sse::new(stream.map(|r| {
+                    Ok::<_, Infallible>(
+                        Event::default()
+                            .data(serde_json::to_string(&r).unwrap_or_default())
+                            .id(r.id.to_string()),
+                    )
+                }))
+                .keep_alive(KeepAlive::default())
+                .into_response(),
+                Err(e) => error(e),
+            };
+            async move { response }
+        };
+        let gw = self.clone();
+        let log_level = move || {
+            let response = json(gw.log_level());
+            async move { response }
+        };
+        let gw = self.clone();
+        let set_log_level = move |Json(level): Json<LogLevel>| {
+            let response = json(gw.set_log_level(&level.directives));
+            async move { response }
+        };
+        let gw = self.clone();
+        let calls = move || {
+            let response = Json(gw.calls()).into_response();
+            async move { response }
+        };
+        let gw = self.clone();
+        let calls_summary = move || {
+            let response = Json(gw.calls_summary()).into_response();
+            async move { response }
+        };
+        let gw = self.clone();
+        let calls_retained = move || {
+            let response = Json(gw.calls_retained()).into_response();
+            async move { response }
+        };
+        let gw = self.clone();
+        let calls_inflight = move || {
+            let response = Json(gw.calls_inflight()).into_response();
+            async move { response }
+        };
+        let gw = self.clone();
+        let threads = move || {
+            let response = json(gw.threads());
+            async move { response }
+        };
+        let gw = self.clone();
+        let descriptors = move || {
+            let response = json(gw.descriptors());
+            async move { response }
+        };
+        let gw = self.clone();
+        let metrics = move || {
+            let response = match gw.metrics() {
+                Ok(v) => ([(header::CONTENT_TYPE, metrics::CONTENT_TYPE)], v).into_response(),
+                Err(e) => error(e),
+            };
+            async move { response }
+        };
+        let mut endpoints = Router::new()
+            .route(&self.endpoint("stats"), get(stats))
+            .route(&self.endpoint("stats/history"), get(stats_history));
+        if self.config.is_enabled(Section::Logs) {
+            endpoints = endpoints.route(&self.endpoint("logs"), get(logs)).route(
+                &self.endpoint("log-level"),
+                get(log_level).put(set_log_level),
+            );
+        }
+        if self.config.is_enabled(Section::Calls) {
+            endpoints = endpoints
+                .route(&self.endpoint("calls"), get(calls))
+                .route(&self.endpoint("calls/summary"), get(calls_summary))
+                .route(&self.endpoint("calls/retained"), get(calls_retained))
+                .route(&self.endpoint("calls/inflight"), get(calls_inflight));
+        }
+        if self.config.is_enabled(Section::Threads) {
+            endpoints = endpoints.route(&self.endpoint("threads"), get(threads));
+        }
+        if self.config.is_enabled(Section::Descriptors) {
+            endpoints = endpoints.route(&self.endpoint("descriptors"), get(descriptors));
+        }
+        if self.config.is_enabled(Section::Metrics) {
+            endpoints = endpoints.route(&self.endpoint("metrics"), get(metrics));
+        }
+        let mut stream = Router::new();
+        if self.config.is_enabled(Section::Logs) {
+            stream = stream.route(&self.endpoint("logs/stream"), get(logs_stream));
+        }
+        let page = self.page(page_path);
+        let page = Router::new()
+            .route(
+                &self.endpoint("w3.css"),
+                get(|| async { ([(header::CONTENT_TYPE, "text/css")], W3_CSS) }),
+            )
+            .route(page_path, get(move || async move { Html(page) }));
+        let auth = self.auth.clone();
+        let endpoints = endpoints.route_layer(middleware::from_fn(move |request, next| {
+            guard(auth.clone(), Access::Endpoint, request, next)
+        }));
+        let auth = self.auth.clone();
+        let stream = stream.route_layer(middleware::from_fn(move |request, next| {
+            guard(auth.clone(), Access::Stream, request, next)
+        }));
+        let auth = self.auth.clone();
+        let page = page.route_layer(middleware::from_fn(move |request, next| {
+            guard(auth.clone(), Access::Page, request, next)
+        }));
+        router.merge(endpoints).merge(stream).merge(page)
+    }
+}
+
+/// Checks the access rules, the page asks for the credentials itself.
+async fn guard(auth: Arc<Auth>, access: Access, request: Request, next: Next) -> Response {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip());
+    let authorization = auth::authorization(access, request.headers(), request.uri().query());
+    // nested routers strip the prefix from the request uri
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(request.uri(), |uri| &uri.0);
+    let auth_request = AuthRequest {
+        method: request.method(),
+        uri,
+        headers: request.headers(),
+        client,
+    };
+    let checked = auth.check(access, &auth_request, authorization.as_deref());
+    match checked {
+        Ok(()) => next.run(request).await,
+        Err(denied) => denied_response(denied),
+    }
+}
+
+fn denied_response(denied: Denied) -> Response {
+    let status = StatusCode::from_u16(denied.status()).expect("valid status");
+    let reason = status.canonical_reason().unwrap_or_default();
+    match denied {
+        Denied::Unauthorized(challenge) => {
+            (status, [(header::WWW_AUTHENTICATE, challenge)], reason).into_response()
+        }
+        Denied::Forbidden => (status, reason).into_response(),
+    }
+}
+
+fn json<T: Serialize>(result: Result<T, Error>) -> Response {
+    match result {
+        Ok(v) => Json(v).into_response(),
+        Err(e) => error(e),
+    }
+}
+
+fn error(e: Error) -> Response {
+    let status = StatusCode::from_u16(e.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    (status, e.to_string()).into_response()
+}
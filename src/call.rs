@@ -1,194 +1,686 @@
+#[cfg(any(feature = "poem", feature = "axum"))]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(any(feature = "poem", feature = "axum"))]
+use std::time::Instant;
 use std::{
-    ops::DerefMut,
+    collections::VecDeque,
     sync::{Arc, Mutex},
-    task::Poll,
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    time::Duration,
 };
 
-use poem::{Body, Endpoint, IntoResponse, Middleware, Response};
-use serde::Serialize;
-use tokio::io::AsyncRead;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Debug)]
+use crate::trace::LogRecord;
+
+mod inflight;
+#[cfg(feature = "axum")]
+mod layer;
+#[cfg(feature = "poem")]
+mod middleware;
+mod retention;
+mod summary;
+use inflight::Inflight;
+pub use inflight::InflightCall;
+#[cfg(any(feature = "poem", feature = "axum"))]
+use inflight::InflightGuard;
+#[cfg(feature = "axum")]
+pub use layer::{CallBody, CallFuture, CallRequestBody, CallService};
+#[cfg(feature = "poem")]
+pub use middleware::CallMiddlewareImpl;
+pub use retention::{
+    CALL_LOGS_SHARE, DEFAULT_ERRORS_PER_ROUTE, DEFAULT_SLOWEST_PER_ROUTE, MAX_CALL_LOGS,
+    RetainedCall, RetainedRoute, Retention,
+};
+#[cfg(any(feature = "poem", feature = "axum"))]
+use summary::UNMATCHED_ROUTE;
+pub use summary::{DURATION_BUCKETS_SECS, RouteSummary, RouteTotals, Summary, WindowSummary};
+
+#[derive(Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Call {
+    /// Taken from the `X-Request-Id` or `traceparent` header or generated, see [`request_id`].
+    pub request_id: String,
     pub timestamp_ms: u64,
+    /// Time until the response body was sent, aborted or failed.
     pub duration_us: u64,
+    /// Time until the handler returned the response head.
+    pub handler_us: u64,
+    /// Time until the first body bytes were passed to the server, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte_us: Option<u64>,
+    pub method: String,
+    /// Protocol version, e.g. `HTTP/1.1`.
+    pub version: String,
+    /// Matched route template or the normalized path, calls are grouped by it.
+    pub route: String,
+    /// Requested path, the query string is processed by [`QueryRule`].
     pub path: String,
-    pub response: CallResponse,
+    /// Status of the response, or of the error returned by the handler.
+    pub status: u16,
+    /// Bytes of the request body read by the handler.
+    pub request_length: u64,
+    /// Bytes of the response body passed to the server.
+    pub response_length: u64,
+    pub outcome: CallOutcome,
 }
 
-#[derive(Serialize, Debug)]
+/// How a call ended.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
-pub enum CallResponse {
-    Ok { length: usize },
-    Error { code: u16 },
+pub enum CallOutcome {
+    /// The response was fully sent.
+    Completed,
+    /// The handler returned an error, the framework sends its status.
+    HandlerError,
+    /// The response body failed while it was sent.
+    BodyError,
+    /// The response was dropped before it was fully sent, usually the client disconnected.
+    ClientAborted,
+}
+
+impl Call {
+    /// Error statuses and responses which failed to be sent, client aborts are not errors.
+    pub fn is_error(&self) -> bool {
+        self.status >= 400 || self.outcome == CallOutcome::BodyError
+    }
+}
+
+#[cfg(test)]
+impl Call {
+    /// Completed `GET` of the route which took a millisecond.
+    pub(crate) fn test(route: &str, timestamp_ms: u64) -> Self {
+        Self {
+            request_id: String::new(),
+            timestamp_ms,
+            duration_us: 1000,
+            handler_us: 1000,
+            first_byte_us: None,
+            method: "GET".to_string(),
+            version: "HTTP/1.1".to_string(),
+            route: route.to_string(),
+            path: route.to_string(),
+            status: 200,
+            request_length: 0,
+            response_length: 0,
+            outcome: CallOutcome::Completed,
+        }
+    }
+
+    pub(crate) fn with_duration_ms(mut self, duration_ms: u64) -> Self {
+        self.duration_us = duration_ms * 1000;
+        self.handler_us = self.duration_us;
+        self
+    }
+
+    pub(crate) fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+/// Default number of calls kept by [`CallMiddleware`].
+pub const DEFAULT_BUFFER_LEN: usize = 100;
+
+/// Ring of the recent calls, the oldest call is dropped when it is full.
+pub struct Buffer {
+    calls: VecDeque<Call>,
+    capacity: usize,
 }
 
-pub type Buffer<const SIZE: usize> = circular_buffer::CircularBuffer<SIZE, Call>;
+impl Buffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            calls: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push_back(&mut self, call: Call) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.calls.len() == self.capacity {
+            self.calls.pop_front();
+        }
+        self.calls.push_back(call);
+    }
+
+    /// Calls, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Call> {
+        self.calls.iter()
+    }
 
-pub type BufferRef<const SIZE: usize> = Arc<Mutex<Buffer<SIZE>>>;
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+pub type BufferRef = Arc<Mutex<Buffer>>;
+
+/// How the query string of a recorded path is kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryRule {
+    /// The query string is dropped.
+    #[default]
+    Strip,
+    Keep,
+    /// Values of all parameters are replaced with [`REDACTED`].
+    RedactAll,
+    /// Values of the listed parameters are replaced with [`REDACTED`].
+    Redact(Vec<String>),
+}
+
+pub const REDACTED: &str = "***";
+
+type Normalizer = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+#[derive(Clone, Default)]
+#[cfg_attr(not(any(feature = "poem", feature = "axum")), allow(dead_code))]
+struct PathRules {
+    normalizer: Option<Normalizer>,
+    query: QueryRule,
+}
+
+/// Log records of a request emitted after a timestamp in microseconds, oldest first.
+type LogLookup = Arc<dyn Fn(&str, u64) -> Vec<LogRecord> + Send + Sync>;
 
 #[derive(Clone)]
-pub struct CallMiddleware<const SIZE: usize> {
-    calls: BufferRef<SIZE>,
+pub struct CallMiddleware {
+    calls: BufferRef,
+    summary: Arc<Mutex<Summary>>,
+    inflight: Arc<Mutex<Inflight>>,
+    retention: Arc<Mutex<Retention>>,
+    rules: Arc<PathRules>,
+    #[cfg_attr(not(any(feature = "poem", feature = "axum")), allow(dead_code))]
+    logs: Option<LogLookup>,
 }
 
-impl<const SIZE: usize> Default for CallMiddleware<SIZE> {
+impl Default for CallMiddleware {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<const SIZE: usize> CallMiddleware<SIZE> {
+impl CallMiddleware {
+    /// Middleware keeping [`DEFAULT_BUFFER_LEN`] recent calls.
     pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_BUFFER_LEN)
+    }
+
+    /// Middleware keeping `capacity` recent calls, summaries and retained calls are not limited
+    /// by it.
+    pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            calls: Arc::new(Mutex::new(Buffer::new())),
+            calls: Arc::new(Mutex::new(Buffer::new(capacity))),
+            summary: Arc::default(),
+            inflight: Arc::default(),
+            retention: Arc::default(),
+            rules: Arc::default(),
+            logs: None,
         }
     }
 
-    pub fn get(&self) -> BufferRef<SIZE> {
-        self.calls.clone()
+    /// Source of the log records attached to the retained calls, which keep up to
+    /// `limit_bytes` of them.
+    pub(crate) fn with_logs(
+        self,
+        limit_bytes: usize,
+        logs: impl Fn(&str, u64) -> Vec<LogRecord> + Send + Sync + 'static,
+    ) -> Self {
+        self.retention
+            .lock()
+            .expect("can lock")
+            .set_logs_limit(limit_bytes);
+        Self {
+            logs: Some(Arc::new(logs)),
+            ..self
+        }
     }
-}
 
-impl Call {
-    pub fn successfull(
-        timestamp_ms: u64,
-        duration: Duration,
-        path: String,
-        reponse_length: usize,
+    /// Groups calls by the result of `normalizer` called with the path, by default the
+    /// matched route template is used and the calls which matched no route are grouped
+    /// under `<unmatched>`.
+    /// The recorded calls are shared with the original middleware.
+    pub fn with_route_normalizer(
+        self,
+        normalizer: impl Fn(&str) -> String + Send + Sync + 'static,
     ) -> Self {
+        let rules = PathRules {
+            normalizer: Some(Arc::new(normalizer)),
+            ..(*self.rules).clone()
+        };
         Self {
-            timestamp_ms,
-            duration_us: duration.as_micros() as u64,
-            path,
-            response: CallResponse::Ok {
-                length: reponse_length,
-            },
+            rules: Arc::new(rules),
+            ..self
         }
     }
 
-    pub fn error(timestamp_ms: u64, duration: Duration, path: String, response_code: u16) -> Self {
+    /// Sets how the query string is recorded, it is stripped by default.
+    /// The recorded calls are shared with the original middleware.
+    pub fn with_query_rule(self, query: QueryRule) -> Self {
+        let rules = PathRules {
+            query,
+            ..(*self.rules).clone()
+        };
         Self {
-            timestamp_ms,
-            duration_us: duration.as_micros() as u64,
-            path,
-            response: CallResponse::Error {
-                code: response_code,
-            },
+            rules: Arc::new(rules),
+            ..self
         }
     }
-}
 
-pub struct CallMiddlewareImpl<const SIZE: usize, E: Endpoint> {
-    endpoint: E,
-    calls: BufferRef<SIZE>,
-}
+    pub fn get(&self) -> BufferRef {
+        self.calls.clone()
+    }
 
-impl<const SIZE: usize, E: Endpoint> Middleware<E> for CallMiddleware<SIZE> {
-    type Output = CallMiddlewareImpl<SIZE, E>;
+    /// Copy of the recorded calls, oldest first.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls
+            .lock()
+            .expect("can lock")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Latency percentiles and error rates per route over sliding windows.
+    pub fn summary(&self) -> Vec<RouteSummary> {
+        self.summary
+            .lock()
+            .expect("can lock")
+            .routes(current_timestamp_ms())
+    }
 
-    fn transform(&self, ep: E) -> Self::Output {
-        CallMiddlewareImpl {
-            endpoint: ep,
-            calls: self.calls.clone(),
+    /// Calls per route since the start.
+    pub fn totals(&self) -> Vec<RouteTotals> {
+        self.summary.lock().expect("can lock").totals()
+    }
+
+    /// The slowest calls and the last errors per route with their log records.
+    pub fn retained(&self) -> Vec<RetainedRoute> {
+        self.retention.lock().expect("can lock").routes()
+    }
+
+    /// Number of the slowest calls and the last errors kept per route, shared by all clones.
+    pub fn set_retention_limits(&self, slowest_per_route: usize, errors_per_route: usize) {
+        self.retention
+            .lock()
+            .expect("can lock")
+            .set_limits(slowest_per_route, errors_per_route);
+    }
+
+    /// Calls started and not finished yet, oldest first.
+    pub fn inflight(&self) -> Vec<InflightCall> {
+        self.inflight.lock().expect("can lock").calls()
+    }
+
+    /// Registers the started call, it is recorded by [`PendingCall::finish`].
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    fn start(
+        &self,
+        method: String,
+        version: String,
+        path: &str,
+        query: Option<&str>,
+        client: Option<String>,
+        request_id: String,
+    ) -> PendingCall {
+        let started = Instant::now();
+        let timestamp_ms = current_timestamp_ms();
+        let recorded_path = self.recorded_path(path, query);
+        let inflight = InflightGuard::new(
+            self.inflight.clone(),
+            started,
+            InflightCall {
+                request_id: request_id.clone(),
+                timestamp_ms,
+                elapsed_us: 0,
+                method: method.clone(),
+                path: recorded_path.clone(),
+                client,
+            },
+        );
+        PendingCall {
+            recorder: self.clone(),
+            request_id,
+            timestamp_ms,
+            started,
+            method,
+            version,
+            route: self.route(path, None),
+            path: recorded_path,
+            request_length: Arc::default(),
+            handler: Duration::ZERO,
+            status: 0,
+            first_byte: None,
+            response_length: 0,
+            _inflight: inflight,
+        }
+    }
+
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    fn route(&self, path: &str, template: Option<&str>) -> String {
+        match (&self.rules.normalizer, template) {
+            (Some(normalizer), _) => normalizer(path),
+            (None, Some(template)) => template.to_string(),
+            // unknown paths would take a route each
+            (None, None) => UNMATCHED_ROUTE.to_string(),
         }
     }
-}
 
-impl<const SIZE: usize, E: Endpoint> Endpoint for CallMiddlewareImpl<SIZE, E> {
-    type Output = Response;
-
-    async fn call(&self, request: poem::Request) -> poem::Result<Self::Output> {
-        let path = request.original_uri().to_string();
-        let timestamp_ms = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .as_ref()
-            .map(Duration::as_millis)
-            .unwrap_or(0) as u64;
-        let now = Instant::now();
-        let res = self.endpoint.call(request).await;
-        let duration = now.elapsed();
-        match res {
-            Ok(response) => {
-                let r = response.into_response();
-                let (parts, body) = r.into_parts();
-                let async_read = body.into_async_read();
-                Ok(Response::from_parts(
-                    parts,
-                    Body::from_async_read(BodyReader {
-                        timestamp_ms,
-                        duration,
-                        path,
-                        calls: self.calls.clone(),
-                        wrapped: async_read,
-                        length: 0,
-                    }),
-                ))
-                // let mut guard = self.calls.lock().expect("can lock mutex");
-                // guard.push_back(Call::successfull(timestamp_ms, duration, path, body.len()));
-                // Ok(Response::from_parts(parts, Body::from_bytes(body)))
-            }
-            Err(err) => {
-                let mut guard = self.calls.lock().expect("can lock mutex");
-                guard.push_back(Call::error(
-                    timestamp_ms,
-                    duration,
-                    path,
-                    err.status().as_u16(),
-                ));
-                Err(err)
-            }
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    fn recorded_path(&self, path: &str, query: Option<&str>) -> String {
+        let query = query
+            .filter(|q| !q.is_empty())
+            .and_then(|q| match &self.rules.query {
+                QueryRule::Strip => None,
+                QueryRule::Keep => Some(q.to_string()),
+                QueryRule::RedactAll => Some(redact(q, |_| true)),
+                QueryRule::Redact(names) => Some(redact(q, |name| names.iter().any(|n| n == name))),
+            });
+        match query {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
         }
     }
+
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    fn record(&self, call: Call) {
+        self.summary.lock().expect("can lock").add(&call);
+        if self.retention.lock().expect("can lock").wants(&call) {
+            // collected without holding the lock, the log buffer has its own
+            let logs = self
+                .logs
+                .as_ref()
+                .map(|logs| logs(&call.request_id, call.timestamp_ms * 1000))
+                .unwrap_or_default();
+            self.retention
+                .lock()
+                .expect("can lock")
+                .add(call.clone(), logs);
+        }
+        self.calls.lock().expect("can lock").push_back(call);
+    }
+}
+
+pub(crate) fn current_timestamp_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .as_ref()
+        .map(Duration::as_millis)
+        .unwrap_or(0) as u64
+}
+
+/// Header with the request id, it is propagated to the handler and added to the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// Field of the span entered for every call, and of the log records emitted while the span
+/// is disabled by the filter.
+pub const REQUEST_ID_FIELD: &str = "request_id";
+
+tokio::task_local! {
+    /// Id of the call executed by the task, set whether the call span is enabled or not.
+    static CURRENT_REQUEST_ID: Arc<str>;
+}
+
+/// Id of the call executed by the current task.
+pub(crate) fn current_request_id() -> Option<Arc<str>> {
+    CURRENT_REQUEST_ID.try_with(Arc::clone).ok()
 }
 
-struct BodyReader<const SIZE: usize, T: AsyncRead + Unpin> {
-    wrapped: T,
+/// The `X-Request-Id` header if it is a short token (letters, digits, `-`, `_`, `.`, `:`),
+/// the trace id of the W3C `traceparent` header, or a new random id.
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub fn request_id(x_request_id: Option<&str>, traceparent: Option<&str>) -> String {
+    let valid_request_id = |id: &&str| {
+        !id.is_empty()
+            && id.len() <= 128
+            && id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+    };
+    // version-trace_id-parent_id-flags
+    let trace_id = |header: &str| {
+        let mut parts = header.split('-');
+        let trace_id = parts.nth(1)?;
+        (trace_id.len() == 32
+            && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+            && trace_id.bytes().any(|b| b != b'0'))
+        .then(|| trace_id.to_ascii_lowercase())
+    };
+    x_request_id
+        .filter(valid_request_id)
+        .map(str::to_string)
+        .or_else(|| traceparent.and_then(trace_id))
+        .unwrap_or_else(new_request_id)
+}
+
+/// 128 random bits formatted as a trace id.
+#[cfg(any(feature = "poem", feature = "axum"))]
+fn new_request_id() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let random = || {
+        // every RandomState has different keys
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u64(count);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", random(), random())
+}
+
+/// Replaces values of the matching query parameters, the names are compared undecoded.
+#[cfg(any(feature = "poem", feature = "axum"))]
+fn redact(query: &str, matches: impl Fn(&str) -> bool) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if matches(name) => format!("{name}={REDACTED}"),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Request part of a call, recorded when the response is finished.
+#[cfg(any(feature = "poem", feature = "axum"))]
+struct PendingCall {
+    recorder: CallMiddleware,
+    request_id: String,
     timestamp_ms: u64,
-    duration: Duration,
+    started: Instant,
+    method: String,
+    version: String,
+    route: String,
     path: String,
-    calls: BufferRef<SIZE>,
-    length: usize,
-}
-
-impl<const SIZE: usize, T: AsyncRead + Unpin> AsyncRead for BodyReader<SIZE, T> {
-    fn poll_read(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        buf: &mut tokio::io::ReadBuf<'_>,
-    ) -> Poll<std::io::Result<()>> {
-        let initial = buf.filled().len();
-        let r = unsafe { std::pin::Pin::new_unchecked(&mut self.deref_mut().wrapped) }
-            .poll_read(cx, buf);
-        if let Poll::Ready(Err(_)) = &r {
-            let mut path = String::new();
-            std::mem::swap(&mut path, &mut self.path);
-            self.calls.lock().expect("can lock").push_back(Call::error(
-                self.timestamp_ms,
-                self.duration,
-                path,
-                u16::MAX,
-            ));
-            return r;
+    /// Updated while the handler reads the request body.
+    request_length: Arc<AtomicU64>,
+    handler: Duration,
+    status: u16,
+    first_byte: Option<Duration>,
+    response_length: u64,
+    _inflight: InflightGuard,
+}
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+impl PendingCall {
+    /// Span entered while the handler is executed, log records are matched to the call by its
+    /// [`REQUEST_ID_FIELD`]. The filter can disable it, so the handler is also executed
+    /// with the id in a task local.
+    fn span(&self) -> tracing::Span {
+        tracing::info_span!("call", request_id = %self.request_id)
+    }
+
+    /// Sets the route from the template matched by the framework.
+    fn matched(&mut self, template: Option<&str>) {
+        let path = self.path.split('?').next().unwrap_or_default();
+        self.route = self.recorder.route(path, template);
+    }
+
+    /// The handler returned a response or an error with the status.
+    fn responded(&mut self, status: u16) {
+        self.handler = self.started.elapsed();
+        self.status = status;
+    }
+
+    /// Response body bytes were passed to the server.
+    fn sent(&mut self, length: usize) {
+        if length > 0 {
+            let started = self.started;
+            self.first_byte.get_or_insert_with(|| started.elapsed());
+            self.response_length += length as u64;
         }
-        match buf.filled().len() - initial {
-            0 => {
-                let mut path = String::new();
-                std::mem::swap(&mut path, &mut self.path);
-                self.calls
-                    .lock()
-                    .expect("can lock")
-                    .push_back(Call::successfull(
-                        self.timestamp_ms,
-                        self.duration,
-                        path,
-                        self.length,
-                    ));
-            }
-            v => self.length += v,
+    }
+
+    /// The handler was dropped before it returned a response, usually the client disconnected.
+    fn abort(mut self) {
+        self.handler = self.started.elapsed();
+        self.finish(CallOutcome::ClientAborted);
+    }
+
+    fn finish(self, outcome: CallOutcome) {
+        let us = |d: Duration| d.as_micros() as u64;
+        self.recorder.record(Call {
+            request_id: self.request_id,
+            timestamp_ms: self.timestamp_ms,
+            duration_us: us(self.started.elapsed()),
+            handler_us: us(self.handler),
+            first_byte_us: self.first_byte.map(us),
+            method: self.method,
+            version: self.version,
+            route: self.route,
+            path: self.path,
+            status: self.status,
+            request_length: self.request_length.load(Ordering::Relaxed),
+            response_length: self.response_length,
+            outcome,
+        });
+    }
+}
+
+#[cfg(all(test, any(feature = "poem", feature = "axum")))]
+mod tests {
+    use super::*;
+
+    fn recorded_path(rule: QueryRule, path: &str, query: Option<&str>) -> String {
+        CallMiddleware::new()
+            .with_query_rule(rule)
+            .recorded_path(path, query)
+    }
+
+    #[test]
+    fn query_rules() {
+        let query = Some("token=abc&page=2&flag");
+        assert_eq!(recorded_path(QueryRule::Strip, "/a", query), "/a");
+        assert_eq!(
+            recorded_path(QueryRule::Keep, "/a", query),
+            "/a?token=abc&page=2&flag"
+        );
+        assert_eq!(
+            recorded_path(QueryRule::RedactAll, "/a", query),
+            "/a?token=***&page=***&flag"
+        );
+        assert_eq!(
+            recorded_path(QueryRule::Redact(vec!["token".to_string()]), "/a", query),
+            "/a?token=***&page=2&flag"
+        );
+        // an empty query string is left out
+        assert_eq!(recorded_path(QueryRule::Keep, "/a", Some("")), "/a");
+        assert_eq!(recorded_path(QueryRule::RedactAll, "/a", None), "/a");
+    }
+
+    #[test]
+    fn redacted_values() {
+        let token = |name: &str| name == "token";
+        // empty values are redacted too
+        assert_eq!(redact("token=&page=", token), "token=***&page=");
+        assert_eq!(
+            redact("token=a&token=b&page=1&token", token),
+            "token=***&token=***&page=1&token"
+        );
+        // the value may contain `=`
+        assert_eq!(redact("token=a=b", token), "token=***");
+        // the names are not decoded
+        assert_eq!(
+            redact("to%6Ben=a&token%5B%5D=b", token),
+            "to%6Ben=a&token%5B%5D=b"
+        );
+        let encoded = |name: &str| name == "token%5B%5D";
+        assert_eq!(redact("token%5B%5D=b", encoded), "token%5B%5D=***");
+        assert_eq!(redact("", token), "");
+    }
+
+    #[test]
+    fn routes() {
+        let calls = CallMiddleware::new();
+        assert_eq!(calls.route("/users/1", Some("/users/:id")), "/users/:id");
+        assert_eq!(calls.route("/users/1", None), UNMATCHED_ROUTE);
+        let calls =
+            calls.with_route_normalizer(|path| path.trim_end_matches(char::is_numeric).to_string());
+        assert_eq!(calls.route("/users/1", Some("/users/:id")), "/users/");
+        assert_eq!(calls.route("/orders/2", None), "/orders/");
+    }
+
+    const TRACEPARENT: &str = "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01";
+
+    fn is_generated(id: &str) -> bool {
+        id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+
+    #[test]
+    fn request_id_from_traceparent() {
+        assert_eq!(
+            request_id(None, Some(TRACEPARENT)),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        for malformed in [
+            "",
+            "00",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473z-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736a-00f067aa0ba902b7-01",
+            // an all-zero trace id is invalid
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        ] {
+            let id = request_id(None, Some(malformed));
+            assert!(is_generated(&id), "{malformed:?}: {id}");
+            assert!(!malformed.contains(&id), "{malformed:?}: {id}");
         }
-        r
+    }
+
+    #[test]
+    fn request_id_header_takes_precedence() {
+        assert_eq!(
+            request_id(Some("req-1.a:b_c"), Some(TRACEPARENT)),
+            "req-1.a:b_c"
+        );
+        // an invalid header falls back to the traceparent
+        for invalid in ["", "has space", "<script>", &"a".repeat(129)] {
+            assert_eq!(
+                request_id(Some(invalid), Some(TRACEPARENT)),
+                "4bf92f3577b34da6a3ce929d0e0e4736",
+                "{invalid:?}"
+            );
+        }
+        assert_eq!(request_id(Some(&"a".repeat(128)), None), "a".repeat(128));
+    }
+
+    #[test]
+    fn generated_request_ids_differ() {
+        let first = request_id(None, None);
+        let second = request_id(None, None);
+        assert!(
+            is_generated(&first) && is_generated(&second),
+            "{first} {second}"
+        );
+        assert_ne!(first, second);
     }
 }
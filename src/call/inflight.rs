@@ -0,0 +1,82 @@
+use std::collections::BTreeMap;
+#[cfg(any(feature = "poem", feature = "axum"))]
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// Request which is executed or whose response is being sent.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InflightCall {
+    pub request_id: String,
+    pub timestamp_ms: u64,
+    pub elapsed_us: u64,
+    pub method: String,
+    pub path: String,
+    /// Remote address, if known to the framework.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+}
+
+struct Entry {
+    started: Instant,
+    call: InflightCall,
+}
+
+/// Registry of the started calls, entries are removed by [`InflightGuard`].
+#[derive(Default)]
+#[cfg_attr(not(any(feature = "poem", feature = "axum")), allow(dead_code))]
+pub(crate) struct Inflight {
+    next_id: u64,
+    entries: BTreeMap<u64, Entry>,
+}
+
+impl Inflight {
+    /// Calls ordered by the start time.
+    pub(crate) fn calls(&self) -> Vec<InflightCall> {
+        self.entries
+            .values()
+            .map(|e| InflightCall {
+                elapsed_us: e.started.elapsed().as_micros() as u64,
+                ..e.call.clone()
+            })
+            .collect()
+    }
+}
+
+/// Keeps the call in the registry until dropped.
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub(crate) struct InflightGuard {
+    registry: Arc<Mutex<Inflight>>,
+    id: u64,
+}
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+impl InflightGuard {
+    pub(crate) fn new(
+        registry: Arc<Mutex<Inflight>>,
+        started: Instant,
+        call: InflightCall,
+    ) -> Self {
+        let id = {
+            let mut inflight = registry.lock().expect("can lock");
+            let id = inflight.next_id;
+            inflight.next_id += 1;
+            inflight.entries.insert(id, Entry { started, call });
+            id
+        };
+        Self { registry, id }
+    }
+}
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.registry
+            .lock()
+            .expect("can lock")
+            .entries
+            .remove(&self.id);
+    }
+}
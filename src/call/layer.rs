@@ -0,0 +1,261 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, ready},
+};
+
+use bytes::Buf;
+use http_body::{Body, Frame, SizeHint};
+use pin_project::{pin_project, pinned_drop};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use super::{
+    CURRENT_REQUEST_ID, CallMiddleware, CallOutcome, PendingCall, REQUEST_ID_HEADER, request_id,
+};
+
+impl<S> Layer<S> for CallMiddleware {
+    type Service = CallService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CallService {
+            inner,
+            recorder: self.clone(),
+        }
+    }
+}
+
+/// Tower service created by [`CallMiddleware`] used as a [`Layer`].
+#[derive(Clone)]
+pub struct CallService<S> {
+    inner: S,
+    recorder: CallMiddleware,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for CallService<S>
+where
+    S: Service<http::Request<CallRequestBody<ReqBody>>, Response = http::Response<ResBody>>,
+    ResBody: Body,
+{
+    type Response = http::Response<CallBody<ResBody>>;
+    type Error = S::Error;
+    type Future = CallFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let header = |name| request.headers().get(name).and_then(|v| v.to_str().ok());
+        let request_id = request_id(header(REQUEST_ID_HEADER), header("traceparent"));
+        // valid header value, it is checked or generated by `request_id`
+        let request_id_value = http::HeaderValue::from_str(&request_id).ok();
+        if let Some(value) = &request_id_value {
+            request
+                .headers_mut()
+                .insert(REQUEST_ID_HEADER, value.clone());
+        }
+        // nested axum routers strip the prefix from the request uri
+        let uri = request
+            .extensions()
+            .get::<axum::extract::OriginalUri>()
+            .map_or(request.uri(), |uri| &uri.0);
+        // present with `into_make_service_with_connect_info::<SocketAddr>`
+        let client = request
+            .extensions()
+            .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+            .map(|info| info.0.to_string());
+        let mut pending = self.recorder.start(
+            request.method().to_string(),
+            format!("{:?}", request.version()),
+            uri.path(),
+            uri.query(),
+            client,
+            request_id,
+        );
+        // present when the layer is added to a router, includes the nesting prefix
+        pending.matched(
+            request
+                .extensions()
+                .get::<axum::extract::MatchedPath>()
+                .map(|p| p.as_str()),
+        );
+        let length = pending.request_length.clone();
+        let request = request.map(|inner| CallRequestBody { inner, length });
+        let span = pending.span();
+        let current: Arc<str> = pending.request_id.as_str().into();
+        let inner = CURRENT_REQUEST_ID.sync_scope(current.clone(), || {
+            span.in_scope(|| self.inner.call(request))
+        });
+        CallFuture {
+            inner,
+            span,
+            current,
+            request_id: request_id_value,
+            pending: Some(pending),
+        }
+    }
+}
+
+/// Request body wrapper, counts bytes read by the inner service.
+#[pin_project]
+pub struct CallRequestBody<B> {
+    #[pin]
+    inner: B,
+    length: Arc<AtomicU64>,
+}
+
+impl<B: Body> Body for CallRequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let res = ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(frame)) = &res
+            && let Some(data) = frame.data_ref()
+        {
+            this.length
+                .fetch_add(data.remaining() as u64, Ordering::Relaxed);
+        }
+        Poll::Ready(res)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Handler future wrapper, records the call as aborted if it is dropped before the response.
+#[pin_project(PinnedDrop)]
+pub struct CallFuture<F> {
+    #[pin]
+    inner: F,
+    span: tracing::Span,
+    /// Id of the call set for the inner future, see [`CURRENT_REQUEST_ID`].
+    current: Arc<str>,
+    request_id: Option<http::HeaderValue>,
+    pending: Option<PendingCall>,
+}
+
+impl<F, B, E> Future for CallFuture<F>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<http::Response<CallBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let res = ready!(CURRENT_REQUEST_ID.sync_scope(this.current.clone(), || {
+            this.span.in_scope(|| this.inner.poll(cx))
+        }));
+        let mut pending = this.pending.take().expect("polled after completion");
+        Poll::Ready(match res {
+            Ok(mut response) => {
+                pending.responded(response.status().as_u16());
+                if let Some(value) = this.request_id.take() {
+                    response
+                        .headers_mut()
+                        .entry(REQUEST_ID_HEADER)
+                        .or_insert(value);
+                }
+                Ok(response.map(|body| {
+                    let mut pending = Some(pending);
+                    if body.is_end_stream() {
+                        CallBody::<B>::finish(&mut pending, CallOutcome::Completed);
+                    }
+                    CallBody {
+                        inner: body,
+                        pending,
+                    }
+                }))
+            }
+            Err(err) => {
+                // a tower error carries no status, the server turns it into 500
+                pending.responded(500);
+                pending.finish(CallOutcome::HandlerError);
+                Err(err)
+            }
+        })
+    }
+}
+
+#[pinned_drop]
+impl<F> PinnedDrop for CallFuture<F> {
+    fn drop(self: Pin<&mut Self>) {
+        if let Some(pending) = self.project().pending.take() {
+            pending.abort();
+        }
+    }
+}
+
+/// Response body wrapper, records the call once the body is sent or dropped.
+#[pin_project(PinnedDrop)]
+pub struct CallBody<B> {
+    #[pin]
+    inner: B,
+    pending: Option<PendingCall>,
+}
+
+impl<B> CallBody<B> {
+    fn finish(pending: &mut Option<PendingCall>, outcome: CallOutcome) {
+        if let Some(p) = pending.take() {
+            p.finish(outcome);
+        }
+    }
+}
+
+impl<B: Body> Body for CallBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let res = ready!(this.inner.as_mut().poll_frame(cx));
+        match &res {
+            Some(Ok(frame)) => {
+                if let (Some(data), Some(pending)) = (frame.data_ref(), this.pending.as_mut()) {
+                    pending.sent(data.remaining());
+                }
+                if this.inner.is_end_stream() {
+                    Self::finish(this.pending, CallOutcome::Completed);
+                }
+            }
+            Some(Err(_)) => Self::finish(this.pending, CallOutcome::BodyError),
+            None => Self::finish(this.pending, CallOutcome::Completed),
+        }
+        Poll::Ready(res)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[pinned_drop]
+impl<B> PinnedDrop for CallBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        // the body was dropped before it was fully sent
+        let this = self.project();
+        Self::finish(this.pending, CallOutcome::ClientAborted);
+    }
+}
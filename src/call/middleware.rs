@@ -0,0 +1,174 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::Poll,
+};
+
+use poem::http::HeaderValue;
+use poem::{Body, Endpoint, IntoResponse, Middleware, PathPattern, Response};
+use tokio::io::AsyncRead;
+use tracing::Instrument;
+
+use super::{
+    CURRENT_REQUEST_ID, CallMiddleware, CallOutcome, PendingCall, REQUEST_ID_HEADER, request_id,
+};
+
+pub struct CallMiddlewareImpl<E: Endpoint> {
+    endpoint: E,
+    recorder: CallMiddleware,
+}
+
+impl<E: Endpoint> Middleware<E> for CallMiddleware {
+    type Output = CallMiddlewareImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        CallMiddlewareImpl {
+            endpoint: ep,
+            recorder: self.clone(),
+        }
+    }
+}
+
+impl<E: Endpoint> Endpoint for CallMiddlewareImpl<E> {
+    type Output = Response;
+
+    async fn call(&self, mut request: poem::Request) -> poem::Result<Self::Output> {
+        let uri = request.original_uri().clone();
+        let header = |name| request.headers().get(name).and_then(|v| v.to_str().ok());
+        let request_id = request_id(header(REQUEST_ID_HEADER), header("traceparent"));
+        // valid header value, it is checked or generated by `request_id`
+        let request_id_value = HeaderValue::from_str(&request_id).ok();
+        if let Some(value) = &request_id_value {
+            request
+                .headers_mut()
+                .insert(REQUEST_ID_HEADER, value.clone());
+        }
+        let pending = self.recorder.start(
+            request.method().to_string(),
+            format!("{:?}", request.version()),
+            uri.path(),
+            uri.query(),
+            request
+                .remote_addr()
+                .as_socket_addr()
+                .map(ToString::to_string),
+            request_id,
+        );
+        let body = request.take_body();
+        if !body.is_empty() {
+            request.set_body(Body::from_async_read(RequestReader {
+                wrapped: body.into_async_read(),
+                length: pending.request_length.clone(),
+            }));
+        }
+        // set when the middleware is inside a route, otherwise the response carries it
+        let outer = request.data::<PathPattern>().cloned();
+        let template =
+            |inner: Option<&PathPattern>| inner.or(outer.as_ref()).map(|t| t.0.to_string());
+        let span = pending.span();
+        let current = pending.request_id.as_str().into();
+        let mut guard = HandlerGuard(Some(pending));
+        let result = CURRENT_REQUEST_ID
+            .scope(current, self.endpoint.call(request).instrument(span))
+            .await;
+        let mut pending = guard.0.take().expect("taken once");
+        match result {
+            Ok(response) => {
+                let r = response.into_response();
+                pending.matched(template(r.data::<PathPattern>()).as_deref());
+                pending.responded(r.status().as_u16());
+                let (mut parts, body) = r.into_parts();
+                if let Some(value) = request_id_value {
+                    parts.headers.entry(REQUEST_ID_HEADER).or_insert(value);
+                }
+                let async_read = body.into_async_read();
+                Ok(Response::from_parts(
+                    parts,
+                    Body::from_async_read(BodyReader {
+                        pending: Some(pending),
+                        wrapped: async_read,
+                    }),
+                ))
+            }
+            Err(err) => {
+                pending.matched(template(err.data::<PathPattern>()).as_deref());
+                pending.responded(err.status().as_u16());
+                pending.finish(CallOutcome::HandlerError);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Records the call as aborted if the handler future is dropped before the response.
+struct HandlerGuard(Option<PendingCall>);
+
+impl Drop for HandlerGuard {
+    fn drop(&mut self) {
+        if let Some(pending) = self.0.take() {
+            pending.abort();
+        }
+    }
+}
+
+/// Counts bytes of the request body read by the handler.
+struct RequestReader<T: AsyncRead + Unpin> {
+    wrapped: T,
+    length: Arc<AtomicU64>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for RequestReader<T> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let initial = buf.filled().len();
+        let r = std::pin::Pin::new(&mut self.wrapped).poll_read(cx, buf);
+        self.length
+            .fetch_add((buf.filled().len() - initial) as u64, Ordering::Relaxed);
+        r
+    }
+}
+
+/// Response body wrapper, records the call once the body is sent or dropped.
+struct BodyReader<T: AsyncRead + Unpin> {
+    wrapped: T,
+    pending: Option<PendingCall>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for BodyReader<T> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let initial = buf.filled().len();
+        let r = std::pin::Pin::new(&mut self.wrapped).poll_read(cx, buf);
+        let read = buf.filled().len() - initial;
+        let outcome = match &r {
+            Poll::Ready(Err(_)) => Some(CallOutcome::BodyError),
+            Poll::Ready(Ok(())) if read == 0 => Some(CallOutcome::Completed),
+            _ => None,
+        };
+        if let Some(pending) = &mut self.pending {
+            pending.sent(read);
+        }
+        if let Some(outcome) = outcome
+            && let Some(pending) = self.pending.take()
+        {
+            pending.finish(outcome);
+        }
+        r
+    }
+}
+
+impl<T: AsyncRead + Unpin> Drop for BodyReader<T> {
+    fn drop(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.finish(CallOutcome::ClientAborted);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::Serialize;
+
+use super::Call;
+use super::summary::{MAX_ROUTES, OTHER_ROUTE};
+use crate::trace::{DEFAULT_BUFFER_SIZE, LogRecord};
+
+pub const DEFAULT_SLOWEST_PER_ROUTE: usize = 5;
+pub const DEFAULT_ERRORS_PER_ROUTE: usize = 10;
+/// Log records kept for a single retained call.
+pub const MAX_CALL_LOGS: usize = 100;
+/// A single retained call keeps log records up to this fraction of the retained logs limit.
+pub const CALL_LOGS_SHARE: usize = 16;
+
+/// Call kept by [`Retention`] with the log records emitted while it was executed.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetainedCall {
+    #[serde(flatten)]
+    pub call: Call,
+    pub logs: Vec<LogRecord>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RetainedRoute {
+    pub route: String,
+    /// The slowest call first.
+    pub slowest: Vec<RetainedCall>,
+    /// The newest error first.
+    pub errors: Vec<RetainedCall>,
+}
+
+#[derive(Default)]
+struct Route {
+    slowest: Vec<RetainedCall>,
+    errors: VecDeque<RetainedCall>,
+}
+
+/// The slowest calls and the last errors per route, kept independently of the recent calls.
+///
+/// Log records of the retained calls are limited by their approximate size in bytes: a call
+/// keeps its newest records up to 1/[`CALL_LOGS_SHARE`] of the limit, and when all retained
+/// records exceed the limit the records of the oldest calls are dropped.
+pub struct Retention {
+    slowest_per_route: usize,
+    errors_per_route: usize,
+    logs_limit: usize,
+    logs_bytes: usize,
+    routes: BTreeMap<String, Route>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            slowest_per_route: DEFAULT_SLOWEST_PER_ROUTE,
+            errors_per_route: DEFAULT_ERRORS_PER_ROUTE,
+            logs_limit: DEFAULT_BUFFER_SIZE,
+            logs_bytes: 0,
+            routes: BTreeMap::new(),
+        }
+    }
+}
+
+impl Retention {
+    /// Changes the limits, calls above the new limits are dropped.
+    pub fn set_limits(&mut self, slowest_per_route: usize, errors_per_route: usize) {
+        self.slowest_per_route = slowest_per_route;
+        self.errors_per_route = errors_per_route;
+        for route in self.routes.values_mut() {
+            let removed = route
+                .slowest
+                .drain(route.slowest.len().min(slowest_per_route)..);
+            self.logs_bytes -= removed.map(|c| logs_size(&c.logs)).sum::<usize>();
+            let removed = route
+                .errors
+                .drain(route.errors.len().min(errors_per_route)..);
+            self.logs_bytes -= removed.map(|c| logs_size(&c.logs)).sum::<usize>();
+        }
+    }
+
+    /// Changes the limit of the retained log records in bytes, the records of the oldest calls
+    /// are dropped to fit it.
+    pub fn set_logs_limit(&mut self, bytes: usize) {
+        self.logs_limit = bytes;
+        self.fit_logs();
+    }
+
+    /// Whether [`Retention::add`] keeps the call, checked before the logs are collected.
+    pub fn wants(&self, call: &Call) -> bool {
+        if call.is_error() {
+            return self.errors_per_route > 0;
+        }
+        match self.routes.get(self.route(&call.route)) {
+            Some(route) => is_slower(&route.slowest, self.slowest_per_route, call),
+            None => self.slowest_per_route > 0,
+        }
+    }
+
+    pub fn add(&mut self, call: Call, mut logs: Vec<LogRecord>) {
+        if !self.wants(&call) {
+            return;
+        }
+        // the newest records are kept, they are the closest to the result of the call
+        let mut bytes = 0;
+        let kept = logs
+            .iter()
+            .rev()
+            .take_while(|r| {
+                bytes += r.size();
+                bytes <= self.logs_limit / CALL_LOGS_SHARE
+            })
+            .count();
+        logs.drain(..logs.len() - kept);
+        self.logs_bytes += logs_size(&logs);
+        let route = self.route(&call.route).to_string();
+        let route = self.routes.entry(route).or_default();
+        let is_error = call.is_error();
+        let retained = RetainedCall { call, logs };
+        let removed = if is_error {
+            route.errors.push_front(retained);
+            (route.errors.len() > self.errors_per_route).then(|| route.errors.pop_back())
+        } else {
+            let position = route
+                .slowest
+                .partition_point(|c| c.call.duration_us >= retained.call.duration_us);
+            route.slowest.insert(position, retained);
+            (route.slowest.len() > self.slowest_per_route).then(|| route.slowest.pop())
+        };
+        if let Some(Some(removed)) = removed {
+            self.logs_bytes -= logs_size(&removed.logs);
+        }
+        self.fit_logs();
+    }
+
+    /// Retained calls sorted by route.
+    pub fn routes(&self) -> Vec<RetainedRoute> {
+        self.routes
+            .iter()
+            .map(|(route, r)| RetainedRoute {
+                route: route.clone(),
+                slowest: r.slowest.clone(),
+                errors: r.errors.iter().cloned().collect(),
+            })
+            .collect()
+    }
+
+    /// Drops log records of the oldest calls until the retained records fit the limit.
+    fn fit_logs(&mut self) {
+        while self.logs_bytes > self.logs_limit {
+            let oldest = self
+                .routes
+                .values_mut()
+                .flat_map(|r| r.slowest.iter_mut().chain(r.errors.iter_mut()))
+                .filter(|c| !c.logs.is_empty())
+                .min_by_key(|c| c.call.timestamp_ms)
+                .expect("retained logs are counted");
+            self.logs_bytes -= logs_size(&oldest.logs);
+            oldest.logs = Vec::new();
+        }
+    }
+
+    fn route<'a>(&self, route: &'a str) -> &'a str {
+        if self.routes.contains_key(route) || self.routes.len() < MAX_ROUTES {
+            route
+        } else {
+            OTHER_ROUTE
+        }
+    }
+}
+
+fn logs_size(logs: &[LogRecord]) -> usize {
+    logs.iter().map(LogRecord::size).sum()
+}
+
+fn is_slower(slowest: &[RetainedCall], limit: usize, call: &Call) -> bool {
+    slowest.len() < limit
+        || slowest
+            .last()
+            .is_some_and(|c| call.duration_us > c.call.duration_us)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logs(messages: &[&str]) -> Vec<LogRecord> {
+        messages
+            .iter()
+            .map(|m| LogRecord {
+                message: m.repeat(100),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn messages(retention: &Retention) -> Vec<Vec<char>> {
+        retention.routes()[0]
+            .errors
+            .iter()
+            .map(|c| {
+                c.logs
+                    .iter()
+                    .map(|r| r.message.chars().next().unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn call_keeps_newest_logs_within_its_share() {
+        let size = logs(&["a"])[0].size();
+        let mut retention = Retention::default();
+        retention.set_logs_limit(size * 2 * CALL_LOGS_SHARE);
+        retention.add(
+            Call::test("/fail", 1).with_status(500),
+            logs(&["a", "b", "c"]),
+        );
+        assert_eq!(messages(&retention), [['b', 'c']]);
+    }
+
+    #[test]
+    fn oldest_calls_lose_logs_over_the_limit() {
+        let size = logs(&["a"])[0].size();
+        let mut retention = Retention::default();
+        // a record per call, the records of all the calls fill the limit
+        retention.set_logs_limit(size * CALL_LOGS_SHARE);
+        retention.set_limits(DEFAULT_SLOWEST_PER_ROUTE, CALL_LOGS_SHARE + 1);
+        for ts in 0..CALL_LOGS_SHARE as u64 {
+            retention.add(Call::test("/fail", ts).with_status(500), logs(&["a", "b"]));
+        }
+        assert!(messages(&retention).iter().all(|m| m == &['b']));
+
+        retention.add(Call::test("/fail", 100).with_status(500), logs(&["c"]));
+        let kept = messages(&retention);
+        assert_eq!(kept.len(), CALL_LOGS_SHARE + 1);
+        assert_eq!(kept[0], ['c']);
+        assert!(kept[1..CALL_LOGS_SHARE].iter().all(|m| m == &['b']));
+        assert!(kept[CALL_LOGS_SHARE].is_empty());
+
+        // dropped calls free their logs
+        retention.set_limits(DEFAULT_SLOWEST_PER_ROUTE, 1);
+        retention.add(Call::test("/fail", 101).with_status(500), logs(&["d"]));
+        retention.set_limits(DEFAULT_SLOWEST_PER_ROUTE, CALL_LOGS_SHARE);
+        for ts in 102..102 + CALL_LOGS_SHARE as u64 - 1 {
+            retention.add(Call::test("/fail", ts).with_status(500), logs(&["e"]));
+        }
+        assert!(messages(&retention).iter().all(|m| m.len() == 1));
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use serde::Serialize;
+
+use super::Call;
+
+const SLOT_MS: u64 = 10_000;
+/// Slots kept for the longest window, one hour.
+const SLOTS: u64 = 360;
+/// Sliding windows reported by [`Summary::routes`], name and length in slots.
+const WINDOWS: [(&str, u64); 3] = [("1m", 6), ("5m", 30), ("1h", SLOTS)];
+/// Routes above the limit are aggregated under [`OTHER_ROUTE`].
+pub(crate) const MAX_ROUTES: usize = 500;
+pub(crate) const OTHER_ROUTE: &str = "<other>";
+/// Route of the calls which matched no route template, e.g. requests of unknown paths.
+#[cfg(any(feature = "poem", feature = "axum"))]
+pub(crate) const UNMATCHED_ROUTE: &str = "<unmatched>";
+/// Upper bounds of the [`RouteTotals`] duration buckets in seconds, the defaults of the
+/// Prometheus client libraries.
+pub const DURATION_BUCKETS_SECS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+/// Sub-buckets per power of two, the bucket width is 1/8 of the value at most.
+const SUB_BUCKET_BITS: u32 = 3;
+
+/// Latency histogram with logarithmic buckets, empty buckets are not stored.
+#[derive(Default, Clone)]
+struct Histogram {
+    buckets: BTreeMap<u16, u64>,
+    count: u64,
+    errors: u64,
+    max_us: u64,
+}
+
+impl Histogram {
+    fn add(&mut self, duration_us: u64, error: bool) {
+        *self.buckets.entry(bucket(duration_us)).or_default() += 1;
+        self.count += 1;
+        self.errors += error as u64;
+        self.max_us = self.max_us.max(duration_us);
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (&b, &c) in &other.buckets {
+            *self.buckets.entry(b).or_default() += c;
+        }
+        self.count += other.count;
+        self.errors += other.errors;
+        self.max_us = self.max_us.max(other.max_us);
+    }
+
+    /// Upper bound of the bucket containing the quantile.
+    fn quantile(&self, q: f64) -> u64 {
+        let rank = ((self.count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (&b, &c) in &self.buckets {
+            seen += c;
+            if seen >= rank {
+                return bucket_upper_bound(b).min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+}
+
+fn bucket(us: u64) -> u16 {
+    let sub_buckets = 1 << SUB_BUCKET_BITS;
+    if us < sub_buckets {
+        return us as u16;
+    }
+    let msb = 63 - us.leading_zeros();
+    let shift = msb - SUB_BUCKET_BITS;
+    (((shift + 1) << SUB_BUCKET_BITS) as u64 + ((us >> shift) & (sub_buckets - 1))) as u16
+}
+
+fn bucket_upper_bound(bucket: u16) -> u64 {
+    let sub_buckets = 1u64 << SUB_BUCKET_BITS;
+    let bucket = bucket as u64;
+    if bucket < sub_buckets {
+        return bucket;
+    }
+    let shift = (bucket >> SUB_BUCKET_BITS) - 1;
+    let base = (sub_buckets + (bucket & (sub_buckets - 1))) << shift;
+    base + (1 << shift) - 1
+}
+
+struct Slot {
+    id: u64,
+    histogram: Histogram,
+}
+
+#[derive(Default)]
+struct Route {
+    slots: VecDeque<Slot>,
+}
+
+impl Route {
+    fn add(&mut self, slot: u64, duration_us: u64, error: bool) {
+        // calls are added when they finish, which can be out of order, the slots are kept sorted
+        let i = self.slots.partition_point(|s| s.id < slot);
+        if self.slots.get(i).is_none_or(|s| s.id != slot) {
+            self.slots.insert(
+                i,
+                Slot {
+                    id: slot,
+                    histogram: Histogram::default(),
+                },
+            );
+        }
+        self.slots[i].histogram.add(duration_us, error);
+        let latest = self.slots.back().expect("slot is added").id;
+        self.expire(latest);
+    }
+
+    fn expire(&mut self, current_slot: u64) {
+        while self
+            .slots
+            .front()
+            .is_some_and(|s| s.id + SLOTS <= current_slot)
+        {
+            self.slots.pop_front();
+        }
+    }
+
+    fn window(&self, current_slot: u64, length: u64) -> Histogram {
+        let mut result = Histogram::default();
+        for slot in self.slots.iter().filter(|s| s.id + length > current_slot) {
+            result.merge(&slot.histogram);
+        }
+        result
+    }
+}
+
+/// Aggregated latency of the calls per route over sliding windows, and totals since the start.
+#[derive(Default)]
+pub struct Summary {
+    routes: HashMap<String, Route>,
+    totals: HashMap<String, RouteTotals>,
+}
+
+/// Calls of a route since the start, the counters only grow.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteTotals {
+    pub route: String,
+    pub count: u64,
+    pub errors: u64,
+    pub duration_sum_us: u64,
+    /// Calls by the first bucket of [`DURATION_BUCKETS_SECS`] which fits their duration, not
+    /// cumulative, longer calls are only in `count`.
+    pub duration_buckets: [u64; DURATION_BUCKETS_SECS.len()],
+}
+
+impl RouteTotals {
+    fn add(&mut self, duration_us: u64, error: bool) {
+        self.count += 1;
+        self.errors += error as u64;
+        self.duration_sum_us += duration_us;
+        let seconds = duration_us as f64 / 1e6;
+        if let Some(i) = DURATION_BUCKETS_SECS.iter().position(|&le| seconds <= le) {
+            self.duration_buckets[i] += 1;
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteSummary {
+    pub route: String,
+    pub windows: Vec<WindowSummary>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowSummary {
+    pub window: &'static str,
+    pub count: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+impl Summary {
+    /// Adds the call to the window slot of its end.
+    pub fn add(&mut self, call: &Call) {
+        let route = call.route.as_str();
+        let route = if self.routes.contains_key(route) || self.routes.len() < MAX_ROUTES {
+            route
+        } else {
+            OTHER_ROUTE
+        };
+        self.routes.entry(route.to_string()).or_default().add(
+            (call.timestamp_ms + call.duration_us / 1000) / SLOT_MS,
+            call.duration_us,
+            call.is_error(),
+        );
+        // totals are never expired, so the routes are limited separately
+        let route = call.route.as_str();
+        let route = if self.totals.contains_key(route) || self.totals.len() < MAX_ROUTES {
+            route
+        } else {
+            OTHER_ROUTE
+        };
+        self.totals
+            .entry(route.to_string())
+            .or_insert_with(|| RouteTotals {
+                route: route.to_string(),
+                ..Default::default()
+            })
+            .add(call.duration_us, call.is_error());
+    }
+
+    /// Totals of every route since the start, sorted by route.
+    pub fn totals(&self) -> Vec<RouteTotals> {
+        let mut result = self.totals.values().cloned().collect::<Vec<_>>();
+        result.sort_by(|a, b| a.route.cmp(&b.route));
+        result
+    }
+
+    /// Routes with calls in the longest window, sorted by route.
+    pub fn routes(&mut self, now_ms: u64) -> Vec<RouteSummary> {
+        let current_slot = now_ms / SLOT_MS;
+        self.routes.retain(|_, r| {
+            r.expire(current_slot);
+            !r.slots.is_empty()
+        });
+        let mut result = self
+            .routes
+            .iter()
+            .map(|(route, r)| RouteSummary {
+                route: route.clone(),
+                windows: WINDOWS
+                    .iter()
+                    .map(|&(window, length)| {
+                        let h = r.window(current_slot, length);
+                        WindowSummary {
+                            window,
+                            count: h.count,
+                            errors: h.errors,
+                            error_rate: if h.count > 0 {
+                                h.errors as f64 / h.count as f64
+                            } else {
+                                0.0
+                            },
+                            p50_us: h.quantile(0.5),
+                            p90_us: h.quantile(0.9),
+                            p99_us: h.quantile(0.99),
+                            max_us: h.max_us,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| a.route.cmp(&b.route));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(summary: &mut Summary, now_ms: u64) -> Vec<(&'static str, u64)> {
+        summary.routes(now_ms)[0]
+            .windows
+            .iter()
+            .map(|w| (w.window, w.count))
+            .collect()
+    }
+
+    #[test]
+    fn long_call_finishing_after_later_calls() {
+        let mut summary = Summary::default();
+        summary.add(&Call::test("/slow", 1_000_000));
+        // started 100 seconds earlier, finished at the same time
+        summary.add(&Call::test("/slow", 900_000).with_duration_ms(100_000));
+        assert_eq!(
+            counts(&mut summary, 1_005_000),
+            [("1m", 2), ("5m", 2), ("1h", 2)]
+        );
+    }
+
+    #[test]
+    fn calls_added_out_of_order() {
+        let mut summary = Summary::default();
+        summary.add(&Call::test("/slow", 1_000_000));
+        summary.add(&Call::test("/slow", 990_000));
+        summary.add(&Call::test("/slow", 700_000));
+        summary.add(&Call::test("/slow", 1_001_000));
+        assert_eq!(
+            counts(&mut summary, 1_005_000),
+            [("1m", 3), ("5m", 3), ("1h", 4)]
+        );
+    }
+}
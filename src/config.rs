@@ -0,0 +1,500 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{AuthConfig, BasicCredentials, IpRange};
+#[cfg(any(feature = "poem", feature = "axum"))]
+use crate::auth::{AuthPredicate, AuthRequest};
+use crate::call::{self, QueryRule};
+use crate::{Error, Groundwork, trace};
+
+/// Environment variable with the path of a TOML or JSON configuration file.
+pub const CONFIG_ENV: &str = "GROUNDWORK_CONFIG";
+const LOG_BUFFER_BYTES_ENV: &str = "GROUNDWORK_LOG_BUFFER_BYTES";
+const CALL_BUFFER_LEN_ENV: &str = "GROUNDWORK_CALL_BUFFER_LEN";
+const SECTIONS_ENV: &str = "GROUNDWORK_SECTIONS";
+const REFRESH_SECS_ENV: &str = "GROUNDWORK_REFRESH_SECS";
+const QUERY_RULE_ENV: &str = "GROUNDWORK_QUERY_RULE";
+const PREFIX_ENV: &str = "GROUNDWORK_PREFIX";
+const AUTH_TOKENS_ENV: &str = "GROUNDWORK_AUTH_TOKENS";
+const AUTH_BASIC_ENV: &str = "GROUNDWORK_AUTH_BASIC";
+const ALLOW_IPS_ENV: &str = "GROUNDWORK_ALLOW_IPS";
+/// Default path of the endpoints.
+pub const DEFAULT_PREFIX: &str = "/groundwork";
+
+/// Optional parts of the status page, a disabled section has neither endpoints nor a tab.
+/// Process stats are always served.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    Logs,
+    /// Recorded, retained and in-flight calls.
+    Calls,
+    /// Threads of the process, their CPU times are sampled every second when enabled.
+    Threads,
+    Descriptors,
+    /// The Prometheus endpoint.
+    Metrics,
+}
+
+impl Section {
+    pub const ALL: [Section; 5] = [
+        Section::Logs,
+        Section::Calls,
+        Section::Threads,
+        Section::Descriptors,
+        Section::Metrics,
+    ];
+
+    /// Threads are read from `/proc`, so the section is available on Linux only.
+    pub fn is_supported(self) -> bool {
+        self != Section::Threads || cfg!(target_os = "linux")
+    }
+}
+
+impl FromStr for Section {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|section| serde_json::to_value(section).is_ok_and(|v| v == s))
+            .ok_or_else(|| Error::InvalidConfig(format!("unknown section {s:?}")))
+    }
+}
+
+/// Runtime configuration, missing fields of a file take the default values.
+///
+/// TOML example:
+/// ```toml
+/// log_buffer_bytes = 1048576
+/// call_buffer_len = 1000
+/// sections = ["logs", "calls", "metrics"]
+/// refresh_secs = 5
+/// query_rule = { redact = ["token"] }
+/// prefix = "/status/api"
+///
+/// [auth]
+/// bearer_tokens = ["secret"]
+/// basic = [{ username = "admin", password = "secret" }]
+/// allow_ips = ["10.0.0.0/8", "127.0.0.1"]
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Approximate size of the kept log records, the log records of the retained calls are
+    /// limited by the same size.
+    pub log_buffer_bytes: usize,
+    /// Number of the kept recent calls.
+    pub call_buffer_len: usize,
+    pub sections: Vec<Section>,
+    /// Interval of the page auto refresh, 0 disables it.
+    pub refresh_secs: u64,
+    /// How query strings of the recorded calls are kept.
+    pub query_rule: QueryRule,
+    /// Path of the endpoints in the router they are registered with.
+    pub prefix: String,
+    pub auth: AuthConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            log_buffer_bytes: trace::DEFAULT_BUFFER_SIZE,
+            call_buffer_len: call::DEFAULT_BUFFER_LEN,
+            sections: Section::ALL
+                .into_iter()
+                .filter(|s| s.is_supported())
+                .collect(),
+            refresh_secs: 0,
+            query_rule: QueryRule::default(),
+            prefix: DEFAULT_PREFIX.to_string(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        toml::from_str(text).map_err(|e| Error::InvalidConfig(e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        serde_json::from_str(text).map_err(|e| Error::InvalidConfig(e.to_string()))
+    }
+
+    /// Reads a `.json` file as JSON and any other file as TOML.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| Error::Config {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        match path.extension() {
+            Some(extension) if extension == "json" => Self::from_json(&text),
+            _ => Self::from_toml(&text),
+        }
+    }
+
+    /// Reads the file named by `GROUNDWORK_CONFIG` if it is set, then overrides its values with
+    /// `GROUNDWORK_LOG_BUFFER_BYTES`, `GROUNDWORK_CALL_BUFFER_LEN`, `GROUNDWORK_REFRESH_SECS`,
+    /// `GROUNDWORK_SECTIONS` (comma separated), `GROUNDWORK_QUERY_RULE` (`strip`, `keep`,
+    /// `redact_all` or `redact:name,...`), `GROUNDWORK_PREFIX`, and the comma separated
+    /// `GROUNDWORK_AUTH_TOKENS`, `GROUNDWORK_AUTH_BASIC` (`username:password`) and
+    /// `GROUNDWORK_ALLOW_IPS`.
+    pub fn from_env() -> Result<Self, Error> {
+        let config = match std::env::var_os(CONFIG_ENV) {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.with_vars(env_var)
+    }
+
+    /// Overrides the values with the variables of [`Config::from_env`] found by `var`.
+    fn with_vars(
+        mut self,
+        var: impl Fn(&str) -> Result<Option<String>, Error>,
+    ) -> Result<Self, Error> {
+        if let Some(bytes) = var(LOG_BUFFER_BYTES_ENV)? {
+            self.log_buffer_bytes = parse(LOG_BUFFER_BYTES_ENV, &bytes)?;
+        }
+        if let Some(len) = var(CALL_BUFFER_LEN_ENV)? {
+            self.call_buffer_len = parse(CALL_BUFFER_LEN_ENV, &len)?;
+        }
+        if let Some(secs) = var(REFRESH_SECS_ENV)? {
+            self.refresh_secs = parse(REFRESH_SECS_ENV, &secs)?;
+        }
+        if let Some(sections) = var(SECTIONS_ENV)? {
+            self.sections = list(&sections).map(str::parse).collect::<Result<_, _>>()?;
+        }
+        if let Some(rule) = var(QUERY_RULE_ENV)? {
+            self.query_rule = match rule.split_once(':') {
+                Some(("redact", names)) => {
+                    QueryRule::Redact(list(names).map(String::from).collect())
+                }
+                _ => serde_json::from_value(serde_json::Value::String(rule.clone())).map_err(
+                    |_| Error::InvalidConfig(format!("{QUERY_RULE_ENV}: unknown rule {rule:?}")),
+                )?,
+            };
+        }
+        if let Some(prefix) = var(PREFIX_ENV)? {
+            self.prefix = prefix;
+        }
+        if let Some(tokens) = var(AUTH_TOKENS_ENV)? {
+            self.auth.bearer_tokens = list(&tokens).map(String::from).collect();
+        }
+        if let Some(credentials) = var(AUTH_BASIC_ENV)? {
+            self.auth.basic = list(&credentials)
+                .map(|c| match c.split_once(':') {
+                    Some((username, password)) => Ok(BasicCredentials {
+                        username: username.to_string(),
+                        password: password.to_string(),
+                    }),
+                    None => Err(Error::InvalidConfig(format!(
+                        "{AUTH_BASIC_ENV}: expected username:password"
+                    ))),
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(ranges) = var(ALLOW_IPS_ENV)? {
+            self.auth.allow_ips = list(&ranges).map(str::parse).collect::<Result<_, _>>()?;
+        }
+        Ok(self)
+    }
+
+    /// Unsupported sections are disabled even when they are listed.
+    pub fn is_enabled(&self, section: Section) -> bool {
+        section.is_supported() && self.sections.contains(&section)
+    }
+
+    /// The prefix with a leading and without a trailing slash, empty for the router root.
+    pub(crate) fn normalized_prefix(&self) -> String {
+        match self.prefix.trim_matches('/') {
+            "" => String::new(),
+            prefix => format!("/{prefix}"),
+        }
+    }
+
+    /// Settings used by the page script, `api` is the location of the endpoints.
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    pub(crate) fn page_json(&self, api: &str) -> String {
+        let sections: Vec<_> = self.sections.iter().filter(|s| s.is_supported()).collect();
+        let page = serde_json::json!({
+            "api": api,
+            "sections": sections,
+            "refreshSecs": self.refresh_secs,
+        });
+        // the value is embedded into a script element
+        page.to_string().replace('<', "\\u003c")
+    }
+}
+
+fn env_var(name: &str) -> Result<Option<String>, Error> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(Error::InvalidConfig(format!("{name}: {e}"))),
+    }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| Error::InvalidConfig(format!("{name}: {e}")))
+}
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+/// Builder of [`Groundwork`], created by [`Groundwork::builder`].
+pub struct GroundworkBuilder {
+    pub(crate) name: String,
+    pub(crate) config: Config,
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    pub(crate) auth_predicate: Option<AuthPredicate>,
+}
+
+impl GroundworkBuilder {
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            config: Config::default(),
+            #[cfg(any(feature = "poem", feature = "axum"))]
+            auth_predicate: None,
+        }
+    }
+
+    /// Replaces all settings, e.g. with [`Config::from_env`], later calls override its values.
+    pub fn config(self, config: Config) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Approximate size of the kept log records, [`trace::DEFAULT_BUFFER_SIZE`] by default.
+    pub fn log_buffer_bytes(mut self, bytes: usize) -> Self {
+        self.config.log_buffer_bytes = bytes;
+        self
+    }
+
+    /// Number of the kept recent calls, [`call::DEFAULT_BUFFER_LEN`] by default.
+    pub fn call_buffer_len(mut self, len: usize) -> Self {
+        self.config.call_buffer_len = len;
+        self
+    }
+
+    /// Enabled sections, all by default.
+    pub fn sections(mut self, sections: impl IntoIterator<Item = Section>) -> Self {
+        self.config.sections = sections.into_iter().collect();
+        self
+    }
+
+    /// Interval of the page auto refresh, it is disabled by default.
+    pub fn refresh_interval(mut self, interval: Duration) -> Self {
+        self.config.refresh_secs = interval.as_secs();
+        self
+    }
+
+    /// How query strings are recorded by [`Groundwork::calls_middleware`], see
+    /// [`call::CallMiddleware::with_query_rule`].
+    pub fn query_rule(mut self, rule: QueryRule) -> Self {
+        self.config.query_rule = rule;
+        self
+    }
+
+    /// Path of the endpoints, [`DEFAULT_PREFIX`] by default. It is relative to the router
+    /// passed to `register_handlers`, the page finds the endpoints relative to its own path.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.config.prefix = prefix.to_string();
+        self
+    }
+
+    /// Requires `Authorization: Bearer <token>` for the endpoints, can be called several times.
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.config.auth.bearer_tokens.push(token.to_string());
+        self
+    }
+
+    /// Requires HTTP Basic credentials for the endpoints, can be called several times.
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.config.auth.basic.push(BasicCredentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+        self
+    }
+
+    /// Forbids the page and the endpoints to clients outside of the ranges. The address of the
+    /// connection is used, `X-Forwarded-For` is not trusted. Axum provides the address when
+    /// served with `into_make_service_with_connect_info::<SocketAddr>`.
+    pub fn allow_ips(mut self, ranges: impl IntoIterator<Item = IpRange>) -> Self {
+        self.config.auth.allow_ips.extend(ranges);
+        self
+    }
+
+    /// Forbids requests of the endpoints, the page and its stylesheet for which `predicate`
+    /// returns false, it is called after the address and credentials are checked.
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    pub fn auth_predicate(
+        mut self,
+        predicate: impl Fn(&AuthRequest) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.auth_predicate = Some(std::sync::Arc::new(predicate));
+        self
+    }
+
+    pub fn build(self) -> Groundwork {
+        Groundwork::from_builder(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<Config, Error> {
+        Config::default().with_vars(|name| {
+            Ok(vars
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.to_string()))
+        })
+    }
+
+    fn query_rule(value: &str) -> Result<QueryRule, Error> {
+        from_vars(&[(QUERY_RULE_ENV, value)]).map(|config| config.query_rule)
+    }
+
+    #[test]
+    fn query_rule_from_env() {
+        assert!(matches!(query_rule("strip"), Ok(QueryRule::Strip)));
+        assert!(matches!(query_rule("keep"), Ok(QueryRule::Keep)));
+        assert!(matches!(query_rule("redact_all"), Ok(QueryRule::RedactAll)));
+        match query_rule("redact:token, key,") {
+            Ok(QueryRule::Redact(names)) => assert_eq!(names, ["token", "key"]),
+            rule => panic!("{rule:?}"),
+        }
+        match query_rule("scramble") {
+            Err(Error::InvalidConfig(message)) => {
+                assert_eq!(message, "GROUNDWORK_QUERY_RULE: unknown rule \"scramble\"")
+            }
+            rule => panic!("{rule:?}"),
+        }
+    }
+
+    #[test]
+    fn sections_from_env() {
+        let config = from_vars(&[(SECTIONS_ENV, "logs, metrics")]).unwrap();
+        assert_eq!(config.sections, [Section::Logs, Section::Metrics]);
+        let config = from_vars(&[(SECTIONS_ENV, "")]).unwrap();
+        assert!(config.sections.is_empty());
+        assert!(matches!(
+            from_vars(&[(SECTIONS_ENV, "logs,graphs")]),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn basic_auth_from_env() {
+        let config = from_vars(&[(AUTH_BASIC_ENV, "admin:se:cret,viewer:")]).unwrap();
+        let credentials: Vec<_> = config
+            .auth
+            .basic
+            .iter()
+            .map(|c| (c.username.as_str(), c.password.as_str()))
+            .collect();
+        assert_eq!(credentials, [("admin", "se:cret"), ("viewer", "")]);
+        assert!(matches!(
+            from_vars(&[(AUTH_BASIC_ENV, "admin")]),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn unset_vars_keep_the_values() {
+        let config = from_vars(&[(REFRESH_SECS_ENV, " 5 ")]).unwrap();
+        assert_eq!(config.refresh_secs, 5);
+        assert_eq!(config.call_buffer_len, call::DEFAULT_BUFFER_LEN);
+        assert_eq!(config.prefix, DEFAULT_PREFIX);
+        assert!(matches!(
+            from_vars(&[(CALL_BUFFER_LEN_ENV, "many")]),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn threads_are_enabled_on_linux_only() {
+        let linux = cfg!(target_os = "linux");
+        assert_eq!(Config::default().is_enabled(Section::Threads), linux);
+        let config = from_vars(&[(SECTIONS_ENV, "threads")]).unwrap();
+        assert_eq!(config.is_enabled(Section::Threads), linux);
+        assert!(Config::default().is_enabled(Section::Logs));
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let text = r#"
+            log_buffer_bytes = 4096
+            call_buffer_len = 10
+            sections = ["logs", "threads"]
+            refresh_secs = 5
+            query_rule = { redact = ["token"] }
+            prefix = "/status/api"
+
+            [auth]
+            bearer_tokens = ["secret"]
+            basic = [{ username = "admin", password = "pass" }]
+            allow_ips = ["10.0.0.0/8", "127.0.0.1/32"]
+        "#;
+        let config = Config::from_toml(text).unwrap();
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "log_buffer_bytes": 4096,
+                "call_buffer_len": 10,
+                "sections": ["logs", "threads"],
+                "refresh_secs": 5,
+                "query_rule": { "redact": ["token"] },
+                "prefix": "/status/api",
+                "auth": {
+                    "bearer_tokens": ["secret"],
+                    "basic": [{ "username": "admin", "password": "pass" }],
+                    "allow_ips": ["10.0.0.0/8", "127.0.0.1/32"],
+                },
+            })
+        );
+
+        let reparsed = Config::from_toml(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json);
+        let reparsed = Config::from_json(&json.to_string()).unwrap();
+        assert_eq!(serde_json::to_value(&reparsed).unwrap(), json);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(matches!(
+            Config::from_toml("buffer = 1"),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            Config::from_json(r#"{"auth": {"tokens": []}}"#),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn missing_file_error_names_the_path() {
+        let error = Config::from_file("/nonexistent/groundwork.toml").unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.starts_with("Cannot read configuration file /nonexistent/groundwork.toml: "),
+            "{message}"
+        );
+    }
+}
@@ -1,13 +1,13 @@
-use poem::error::InternalServerError;
-use poem::{Error, Result, handler, http::StatusCode};
 use serde::Serialize;
 
+use crate::Error;
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Descriptor {
-    n: u32,
-    kind: DescriptorKind,
-    details: String,
+    pub n: u32,
+    pub kind: DescriptorKind,
+    pub details: String,
 }
 
 #[derive(Serialize, Debug)]
@@ -21,20 +21,19 @@ pub enum DescriptorKind {
     Other,
 }
 
+/// Lists open file descriptors of the current process.
 #[cfg(target_os = "macos")]
-#[handler]
-pub fn descriptors() -> Result<String> {
+pub fn descriptors() -> Result<Vec<Descriptor>, Error> {
     let pid = std::process::id() as i32;
-    let info = libproc::proc_pid::pidinfo::<libproc::bsd_info::BSDInfo>(pid, 0)
-        .map_err(|e| Error::from_string(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    let info =
+        libproc::proc_pid::pidinfo::<libproc::bsd_info::BSDInfo>(pid, 0).map_err(Error::Process)?;
     let fds = libproc::proc_pid::listpidinfo::<libproc::file_info::ListFDs>(
         pid,
         info.pbi_nfiles as usize,
     )
-    .map_err(|e| Error::from_string(e, StatusCode::INTERNAL_SERVER_ERROR))?;
+    .map_err(Error::Process)?;
 
-    let descriptors = fds.iter().map(convert_mac_fd).collect::<Vec<_>>();
-    serde_json::to_string(&descriptors).map_err(InternalServerError)
+    Ok(fds.iter().map(convert_mac_fd).collect())
 }
 
 #[cfg(target_os = "macos")]
@@ -74,19 +73,15 @@ fn mac_descriptor(fd: &libproc::file_info::ProcFDInfo) -> (DescriptorKind, Strin
     }
 }
 
+/// Lists open file descriptors of the current process.
 #[cfg(target_os = "linux")]
-#[handler]
-pub fn descriptors() -> Result<String> {
-    let map_err =
-        |e: procfs::ProcError| Error::from_string(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR);
-    let process = procfs::process::Process::myself().map_err(map_err)?;
+pub fn descriptors() -> Result<Vec<Descriptor>, Error> {
+    let process = procfs::process::Process::myself()?;
     let sockets = linux::sockets(&process);
-    let descriptors = process
-        .fd()
-        .map_err(map_err)?
+    Ok(process
+        .fd()?
         .map(|d| linux::descriptor(d, &sockets))
-        .collect::<Vec<_>>();
-    serde_json::to_string(&descriptors).map_err(InternalServerError)
+        .collect())
 }
 
 #[cfg(target_os = "linux")]
@@ -162,15 +157,15 @@ mod linux {
         let udp = process
             .udp()
             .into_iter()
-            .chain(process.udp6().into_iter())
-            .flat_map(|v| v)
+            .chain(process.udp6())
+            .flatten()
             .map(|s| (s.inode, Either::Left(s)));
 
         let tcp = process
             .tcp()
             .into_iter()
-            .chain(process.tcp6().into_iter())
-            .flat_map(|v| v)
+            .chain(process.tcp6())
+            .flatten()
             .map(|s| (s.inode, Either::Right(s)));
 
         udp.chain(tcp).collect::<Vec<_>>()
@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Cannot read process information: {0}")]
+    Process(String),
+    #[error("Hostname is not a valid UTF-8 string: {0}")]
+    Hostname(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Cannot read configuration file {}: {source}", path.display())]
+    Config {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("The {0} is not configured")]
+    NotConfigured(&'static str),
+}
+
+impl Error {
+    /// HTTP status code to report the error with.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::InvalidFilter(_) => 400,
+            Self::NotConfigured(_) => 404,
+            _ => 500,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<procfs::ProcError> for Error {
+    fn from(e: procfs::ProcError) -> Self {
+        Self::Process(e.to_string())
+    }
+}
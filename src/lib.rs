@@ -1,61 +1,179 @@
-use std::sync::{Arc, Mutex};
-
-use poem::error::InternalServerError;
-use poem::middleware::AddData;
-use poem::web::{Data, Html, WithContentType};
-use poem::{EndpointExt, IntoResponse};
-use poem::{Result, handler};
-use poem::{Route, get};
+use std::sync::{Arc, Mutex, Once};
+
 use trace::Buffer;
 use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, reload};
+pub mod auth;
+#[cfg(feature = "axum")]
+mod axum;
 pub mod call;
+mod config;
 pub mod descriptors;
+mod error;
+pub mod metrics;
+#[cfg(feature = "poem")]
+mod poem;
+#[cfg(feature = "poem")]
+mod server;
 pub mod stat;
 pub mod trace;
 
-pub type DefaultGroundwork = Groundwork<{ trace::DEFAULT_BUFFER_SIZE }, 100>;
+pub use config::{CONFIG_ENV, Config, DEFAULT_PREFIX, GroundworkBuilder, Section};
+pub use error::Error;
+/// Handle of [`Groundwork::serve`], both require the `poem` feature.
+#[cfg(feature = "poem")]
+pub use server::StatusServer;
+
+#[cfg(any(feature = "poem", feature = "axum"))]
+const INDEX_HTML: &str = include_str!("index.html");
+#[cfg(any(feature = "poem", feature = "axum"))]
+const W3_CSS: &str = include_str!("w3.css");
+/// Replaced with the page settings in [`INDEX_HTML`].
+#[cfg(any(feature = "poem", feature = "axum"))]
+const PAGE_CONFIG_PLACEHOLDER: &str = "/* page config */ {}";
+/// Replaced with the location of the endpoints in [`INDEX_HTML`] attributes.
+#[cfg(any(feature = "poem", feature = "axum"))]
+const PAGE_API_PLACEHOLDER: &str = "{{api}}";
 
-pub struct Groundwork<const LOG_SIZE: usize, const CALL_SIZE: usize> {
+#[derive(Clone)]
+pub struct Groundwork {
     stats_data: Arc<stat::StatsData>,
-    logs: Arc<Mutex<Buffer<LOG_SIZE>>>,
-    calls_middleware: call::CallMiddleware<CALL_SIZE>,
+    stats_history: Arc<Mutex<stat::History>>,
+    sampling: Arc<Once>,
+    logs: Arc<Mutex<Buffer>>,
+    calls_middleware: call::CallMiddleware,
+    log_level: trace::LevelControl,
+    config: Arc<Config>,
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    auth: Arc<auth::Auth>,
 }
 
-impl<const LOG_SIZE: usize, const CALL_SIZE: usize> Groundwork<LOG_SIZE, CALL_SIZE> {
+impl Groundwork {
+    /// Groundwork with the default [`Config`].
+    ///
+    /// The resource history is sampled by a `groundwork-stats` thread, which is started
+    /// once the handlers are registered or [`Groundwork::start_sampling`] is called.
     pub fn new(name: &str) -> Self {
+        Self::builder(name).build()
+    }
+
+    pub fn builder(name: &str) -> GroundworkBuilder {
+        GroundworkBuilder::new(name)
+    }
+
+    pub(crate) fn from_builder(builder: GroundworkBuilder) -> Self {
+        let mut config = builder.config;
+        config.prefix = config.normalized_prefix();
+        let logs = Arc::new(Mutex::new(Buffer::new(config.log_buffer_bytes)));
+        let calls_middleware = call::CallMiddleware::with_capacity(config.call_buffer_len)
+            .with_query_rule(config.query_rule.clone())
+            // retained calls keep at most as many log records as the log buffer
+            .with_logs(config.log_buffer_bytes, {
+                let logs = logs.clone();
+                move |request_id, from_us| {
+                    logs.lock().expect("can lock buffer mutex").request_records(
+                        request_id,
+                        from_us,
+                        call::MAX_CALL_LOGS,
+                    )
+                }
+            });
+        let stats_data = Arc::new(stat::StatsData::new(&builder.name));
         Self {
-            stats_data: Arc::new(stat::StatsData::new(name)),
-            logs: Arc::new(Mutex::new(Buffer::new())),
-            calls_middleware: call::CallMiddleware::new(),
+            stats_data,
+            stats_history: Arc::default(),
+            sampling: Arc::new(Once::new()),
+            logs,
+            calls_middleware,
+            log_level: trace::LevelControl::default(),
+            #[cfg(any(feature = "poem", feature = "axum"))]
+            auth: Arc::new(auth::Auth::new(&config.auth, builder.auth_predicate)),
+            config: Arc::new(config),
         }
     }
 
-    pub fn register_handlers(&self, route: Route, page_path: &str) -> Route {
-        route
-            .at(
-                "/groundwork/stats",
-                get(stat::stats).with(AddData::new(self.stats_data.clone())),
-            )
-            .at(
-                "/groundwork/logs",
-                get(logs).with(AddData::new(self.logs.clone())),
-            )
-            .at(
-                "/groundwork/calls",
-                get(calls).with(AddData::new(self.calls_middleware().get())),
-            )
-            .at("/groundwork/descriptors", get(descriptors::descriptors))
-            .at("/groundwork/w3.css", css)
-            .at(page_path, index)
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
+    /// Path of the endpoint in the router passed to `register_handlers`.
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    fn endpoint(&self, name: &str) -> String {
+        format!("{}/{name}", self.config.prefix)
+    }
+
+    /// The status page served at `page_path` with the settings used by its script.
+    #[cfg(any(feature = "poem", feature = "axum"))]
+    fn page(&self, page_path: &str) -> String {
+        let api = relative_prefix(page_path, &self.config.prefix);
+        INDEX_HTML
+            .replace(PAGE_API_PLACEHOLDER, &html_attribute(&api))
+            .replace(PAGE_CONFIG_PLACEHOLDER, &self.config.page_json(&api))
+    }
+
+    /// Registers stdout subscriber, the initial filter is taken from `RUST_LOG`, `info` by default.
     pub fn register_stdout_tracing_subscriber(&self) {
-        tracing_subscriber::fmt::Subscriber::builder()
-            .with_ansi(false)
-            .with_writer(self.trace_writer_stdout())
+        let directives = std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+        let filter = match self.log_filter_layer(&directives) {
+            Ok(filter) => filter,
+            Err(_) => self.log_filter_layer("info").expect("valid directives"),
+        };
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(tracing_subscriber::fmt::layer().with_ansi(false))
+            .with(self.tracing_layer())
             .init();
     }
 
+    /// Filter which can be changed with [`Groundwork::set_log_level`] and the
+    /// `log-level` endpoint, add it as the first layer of the subscriber.
+    /// It also sets the `log` crate max level.
+    pub fn log_filter_layer<S>(
+        &self,
+        directives: &str,
+    ) -> Result<reload::Layer<EnvFilter, S>, Error>
+    where
+        S: tracing::Subscriber + 'static,
+    {
+        self.log_level.layer(directives)
+    }
+
+    /// Layer which stores structured events in the logs buffer.
+    pub fn tracing_layer(&self) -> trace::LogLayer {
+        trace::LogLayer::new(self.logs.clone())
+    }
+
+    /// Wraps the `log` crate logger to store its records in the logs buffer.
+    ///
+    /// The records are filtered by the directives of [`Groundwork::set_log_level`] and the
+    /// `log-level` endpoint, initially those of [`Groundwork::log_filter_layer`] or `RUST_LOG`,
+    /// everything by default, and then by `logger`.
+    pub fn spy_logger<T: log::Log>(&self, logger: T) -> trace::SpyLogger<T> {
+        let directives = std::env::var("RUST_LOG")
+            .ok()
+            .filter(|d| EnvFilter::try_new(d).is_ok())
+            .unwrap_or_else(|| "trace".to_string());
+        trace::SpyLogger::with_buffer(self.logs.clone(), logger)
+            .with_filter(self.log_level.log_filter(&directives))
+    }
+
+    /// Installs [`Groundwork::spy_logger`] as the `log` crate logger, for services which use
+    /// `log` without a tracing subscriber. Subscribers initialized with `init()` already
+    /// forward `log` records to tracing.
+    ///
+    /// It also sets the `log` crate max level, which follows the log level changes.
+    pub fn register_logger<T: log::Log + 'static>(
+        &self,
+        logger: T,
+    ) -> Result<(), log::SetLoggerError> {
+        log::set_boxed_logger(Box::new(self.spy_logger(logger)))?;
+        // the default is `Off`, which drops every record before the logger
+        self.log_level.apply_log_max_level();
+        Ok(())
+    }
+
     pub fn trace_writer_stdout(&self) -> impl for<'a> MakeWriter<'a> + 'static {
         trace::StdoutTraceWriterMaker::new(self.logs.clone())
     }
@@ -67,32 +185,118 @@ impl<const LOG_SIZE: usize, const CALL_SIZE: usize> Groundwork<LOG_SIZE, CALL_SI
         trace::TraceWriterWrapperMaker::new(self.logs.clone(), writer)
     }
 
-    pub fn calls_middleware(&self) -> call::CallMiddleware<CALL_SIZE> {
+    pub fn calls_middleware(&self) -> call::CallMiddleware {
         self.calls_middleware.clone()
     }
-}
 
-#[handler]
-fn logs(
-    buffer: Data<&std::sync::Arc<std::sync::Mutex<trace::Buffer<{ trace::DEFAULT_BUFFER_SIZE }>>>>,
-) -> Result<String> {
-    serde_json::to_string(&buffer.lock().unwrap().get_traces().unwrap())
-        .map_err(InternalServerError)
-}
+    pub fn stats(&self) -> Result<stat::Stats, Error> {
+        let current = stat::sample(&self.stats_data, call::current_timestamp_ms())?;
+        let mut stats = stat::stats_with(&self.stats_data, &current)?;
+        let history = self.stats_history.lock().expect("can lock history mutex");
+        stats.cpu_usage = history.cpu_usage(&current, stats.cpu_cores);
+        stats.io_rates = history.io_rates(&current);
+        Ok(stats)
+    }
+
+    /// Starts the thread which samples the resource usage every [`stat::RECENT_INTERVAL`] for
+    /// [`Groundwork::stats_history`], the CPU and I/O rates of [`Groundwork::stats`] and the
+    /// thread CPU usage. The handlers start it, call it when only the API is used. The thread
+    /// stops once all clones of the groundwork are dropped.
+    pub fn start_sampling(&self) {
+        self.sampling.call_once(|| {
+            stat::History::start_sampling(
+                &self.stats_history,
+                self.stats_data.clone(),
+                self.config.is_enabled(Section::Threads),
+            );
+        });
+    }
 
-// FIXME convert to implementation of Endpoint
-#[handler]
-fn calls(buffer: Data<&call::BufferRef<100>>) -> Result<String> {
-    serde_json::to_string(&buffer.lock().unwrap().iter().collect::<Vec<_>>())
-        .map_err(InternalServerError)
+    /// Samples taken in the background since [`Groundwork::start_sampling`].
+    pub fn stats_history(&self) -> stat::StatsHistory {
+        self.stats_history
+            .lock()
+            .expect("can lock history mutex")
+            .history()
+    }
+
+    pub fn logs(&self, filter: &trace::LogFilter) -> Result<Vec<trace::LogRecord>, Error> {
+        filter.apply(self.logs.lock().expect("can lock buffer mutex").records())
+    }
+
+    pub fn log_level(&self) -> Result<trace::LogLevel, Error> {
+        self.log_level.get()
+    }
+
+    pub fn set_log_level(&self, directives: &str) -> Result<trace::LogLevel, Error> {
+        self.log_level.set(directives)
+    }
+
+    pub fn logs_stream(
+        &self,
+        filter: &trace::LogFilter,
+    ) -> Result<impl futures_util::Stream<Item = trace::LogRecord> + Send + 'static, Error> {
+        filter.stream(&self.logs)
+    }
+
+    pub fn calls(&self) -> Vec<call::Call> {
+        self.calls_middleware.calls()
+    }
+
+    /// Per-route latency percentiles and error rates of the recorded calls.
+    pub fn calls_summary(&self) -> Vec<call::RouteSummary> {
+        self.calls_middleware.summary()
+    }
+
+    /// The slowest calls and the last errors per route with the log records emitted meanwhile.
+    pub fn calls_retained(&self) -> Vec<call::RetainedRoute> {
+        self.calls_middleware.retained()
+    }
+
+    /// Calls which are executed or whose response is being sent.
+    pub fn calls_inflight(&self) -> Vec<call::InflightCall> {
+        self.calls_middleware.inflight()
+    }
+
+    /// Threads of the process with their CPU usage over the latest sampling interval.
+    pub fn threads(&self) -> Result<Vec<stat::Thread>, Error> {
+        let usage = self
+            .stats_history
+            .lock()
+            .expect("can lock history mutex")
+            .thread_usage();
+        stat::threads(&self.stats_data, &usage)
+    }
+
+    pub fn descriptors(&self) -> Result<Vec<descriptors::Descriptor>, Error> {
+        descriptors::descriptors()
+    }
+
+    /// Stats and recorded calls in the Prometheus text format.
+    pub fn metrics(&self) -> Result<String, Error> {
+        Ok(metrics::render(
+            &self.stats()?,
+            &self.calls_middleware.totals(),
+        ))
+    }
 }
 
-#[handler]
-fn css() -> WithContentType<&'static str> {
-    include_str!("w3.css").with_content_type("text/css")
+/// The prefix relative to the page path, so the page works when the router is nested or is
+/// behind a proxy which strips a path prefix. Both paths are relative to the same router.
+#[cfg(any(feature = "poem", feature = "axum"))]
+fn relative_prefix(page_path: &str, prefix: &str) -> String {
+    let depth = page_path.trim_start_matches('/').matches('/').count();
+    let relative = format!("{}{}", "../".repeat(depth), prefix.trim_start_matches('/'));
+    match relative.trim_end_matches('/') {
+        "" => ".".to_string(),
+        relative => relative.to_string(),
+    }
 }
 
-#[handler]
-fn index() -> Html<&'static str> {
-    Html(include_str!("index.html"))
+#[cfg(any(feature = "poem", feature = "axum"))]
+fn html_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
 }
@@ -0,0 +1,281 @@
+use std::fmt::{Display, Write};
+
+use crate::call::{DURATION_BUCKETS_SECS, RouteTotals};
+use crate::stat::Stats;
+
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+const GAUGE: &str = "gauge";
+const COUNTER: &str = "counter";
+const HISTOGRAM: &str = "histogram";
+
+/// Renders process stats and call totals in the Prometheus text format.
+///
+/// Call metrics are counters and a histogram since the start, independent of the call buffer,
+/// so `rate` and `histogram_quantile` work with them.
+pub fn render(stats: &Stats, routes: &[RouteTotals]) -> String {
+    let mut w = Writer::default();
+    w.header("groundwork_info", GAUGE, "Process name and hostname");
+    w.sample(
+        "groundwork_info",
+        &format!(
+            "name=\"{}\",hostname=\"{}\"",
+            escape(&stats.name),
+            escape(&stats.hostname)
+        ),
+        1,
+    );
+    w.single(
+        "process_resident_memory_bytes",
+        GAUGE,
+        "Resident memory size in bytes",
+        stats.mem_rss,
+    );
+    w.single(
+        "groundwork_resident_memory_peak_bytes",
+        GAUGE,
+        "Peak resident memory size in bytes",
+        stats.mem_rss_peak,
+    );
+    w.single(
+        "process_virtual_memory_bytes",
+        GAUGE,
+        "Virtual memory size in bytes",
+        stats.mem_virtual,
+    );
+    w.single(
+        "groundwork_allocated_bytes",
+        GAUGE,
+        "Bytes allocated by the global allocator",
+        stats.mem_allocated_bytes,
+    );
+    w.single(
+        "groundwork_allocations",
+        GAUGE,
+        "Live allocations of the global allocator",
+        stats.allocations,
+    );
+    w.single(
+        "process_open_fds",
+        GAUGE,
+        "Number of open file descriptors",
+        stats.fd_count,
+    );
+    w.single(
+        "groundwork_threads",
+        GAUGE,
+        "Number of threads",
+        stats.threads_count,
+    );
+    w.single(
+        "process_cpu_seconds_total",
+        COUNTER,
+        "Total user and system CPU time spent in seconds",
+        seconds(stats.user_time_us + stats.system_time_us),
+    );
+    w.header(
+        "groundwork_cpu_seconds_total",
+        COUNTER,
+        "CPU time spent in seconds by mode",
+    );
+    w.sample(
+        "groundwork_cpu_seconds_total",
+        "mode=\"user\"",
+        seconds(stats.user_time_us),
+    );
+    w.sample(
+        "groundwork_cpu_seconds_total",
+        "mode=\"system\"",
+        seconds(stats.system_time_us),
+    );
+    w.single(
+        "groundwork_cpu_cores",
+        GAUGE,
+        "CPUs available to the process, limited by the affinity and the cgroup quota",
+        stats.cpu_cores,
+    );
+    w.single(
+        "process_start_time_seconds",
+        GAUGE,
+        "Start time of the process since unix epoch in seconds",
+        stats.start_time_ms as f64 / 1e3,
+    );
+
+    w.header(
+        "groundwork_calls_total",
+        COUNTER,
+        "Calls since the start by route and result, error is a 4xx or 5xx status or a failed response body",
+    );
+    for t in routes {
+        let route = escape(&t.route);
+        w.sample(
+            "groundwork_calls_total",
+            &format!("route=\"{route}\",result=\"ok\""),
+            t.count - t.errors,
+        );
+        w.sample(
+            "groundwork_calls_total",
+            &format!("route=\"{route}\",result=\"error\""),
+            t.errors,
+        );
+    }
+    w.header(
+        "groundwork_call_duration_seconds",
+        HISTOGRAM,
+        "Duration of the calls since the start by route",
+    );
+    for t in routes {
+        let route = escape(&t.route);
+        let mut cumulative = 0;
+        for (le, count) in DURATION_BUCKETS_SECS.iter().zip(t.duration_buckets) {
+            cumulative += count;
+            w.sample(
+                "groundwork_call_duration_seconds_bucket",
+                &format!("route=\"{route}\",le=\"{le}\""),
+                cumulative,
+            );
+        }
+        w.sample(
+            "groundwork_call_duration_seconds_bucket",
+            &format!("route=\"{route}\",le=\"+Inf\""),
+            t.count,
+        );
+        w.sample(
+            "groundwork_call_duration_seconds_sum",
+            &format!("route=\"{route}\""),
+            seconds(t.duration_sum_us),
+        );
+        w.sample(
+            "groundwork_call_duration_seconds_count",
+            &format!("route=\"{route}\""),
+            t.count,
+        );
+    }
+    w.0
+}
+
+#[derive(Default)]
+struct Writer(String);
+
+impl Writer {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        _ = writeln!(self.0, "# HELP {name} {help}");
+        _ = writeln!(self.0, "# TYPE {name} {kind}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &str, value: impl Display) {
+        if labels.is_empty() {
+            _ = writeln!(self.0, "{name} {value}");
+        } else {
+            _ = writeln!(self.0, "{name}{{{labels}}} {value}");
+        }
+    }
+
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.header(name, kind, help);
+        self.sample(name, "", value);
+    }
+}
+
+fn seconds(us: u64) -> f64 {
+    us as f64 / 1e6
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> Stats {
+        Stats {
+            name: "name \"quoted\"".to_string(),
+            hostname: "host\\a\nb".to_string(),
+            mem_rss: 1024,
+            mem_rss_peak: 2048,
+            mem_virtual: 4096,
+            mem_allocated_bytes: 512,
+            allocations: 8,
+            fd_count: 5,
+            threads_count: 3,
+            user_time_us: 1_500_000,
+            system_time_us: 500_000,
+            start_time_ms: 1_700_000_000_500,
+            cpu_cores: 1.5,
+            cpu_usage: Vec::new(),
+            io: None,
+            io_rates: Vec::new(),
+            cgroup: None,
+        }
+    }
+
+    #[test]
+    fn renders_the_exposition_format() {
+        let mut buckets = [0; DURATION_BUCKETS_SECS.len()];
+        // 4ms, 8ms and 200ms, the 20s call is above the last bucket
+        buckets[0] = 1;
+        buckets[1] = 1;
+        buckets[5] = 1;
+        let routes = [RouteTotals {
+            route: "/users/{id}".to_string(),
+            count: 4,
+            errors: 1,
+            duration_sum_us: 20_212_000,
+            duration_buckets: buckets,
+        }];
+        let text = render(&stats(), &routes);
+        let lines: Vec<_> = text.lines().collect();
+        let has = |line: &str| lines.contains(&line);
+
+        assert!(has(
+            "# HELP process_resident_memory_bytes Resident memory size in bytes"
+        ));
+        assert!(has("# TYPE process_resident_memory_bytes gauge"));
+        assert!(has("process_resident_memory_bytes 1024"));
+        assert!(has(
+            r#"groundwork_info{name="name \"quoted\"",hostname="host\\a\nb"} 1"#
+        ));
+        assert!(has("# TYPE process_cpu_seconds_total counter"));
+        assert!(has("process_cpu_seconds_total 2"));
+        assert!(has(r#"groundwork_cpu_seconds_total{mode="user"} 1.5"#));
+        assert!(has("process_start_time_seconds 1700000000.5"));
+
+        assert!(has("# TYPE groundwork_calls_total counter"));
+        assert!(has(
+            r#"groundwork_calls_total{route="/users/{id}",result="ok"} 3"#
+        ));
+        assert!(has(
+            r#"groundwork_calls_total{route="/users/{id}",result="error"} 1"#
+        ));
+
+        assert!(has("# TYPE groundwork_call_duration_seconds histogram"));
+        let bucket = |le: &str| {
+            let prefix = format!(
+                r#"groundwork_call_duration_seconds_bucket{{route="/users/{{id}}",le="{le}"}} "#
+            );
+            lines
+                .iter()
+                .find_map(|l| l.strip_prefix(&prefix))
+                .unwrap_or_else(|| panic!("no bucket {le}"))
+                .to_string()
+        };
+        assert_eq!(bucket("0.005"), "1");
+        assert_eq!(bucket("0.01"), "2");
+        assert_eq!(bucket("0.1"), "2");
+        assert_eq!(bucket("0.25"), "3");
+        assert_eq!(bucket("10"), "3");
+        assert_eq!(bucket("+Inf"), "4");
+        assert!(has(
+            r#"groundwork_call_duration_seconds_count{route="/users/{id}"} 4"#
+        ));
+        assert!(has(
+            r#"groundwork_call_duration_seconds_sum{route="/users/{id}"} 20.212"#
+        ));
+    }
+}
@@ -0,0 +1,242 @@
+// poem handlers return poem::Result, its error type is large by design
+#![allow(clippy::result_large_err)]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+
+use ::poem::endpoint::make_sync;
+use ::poem::error::InternalServerError;
+use ::poem::http::{StatusCode, header};
+use ::poem::middleware::AddData;
+use ::poem::web::sse::{Event, SSE};
+use ::poem::web::{Data, Html, Json, WithContentType};
+use ::poem::{Endpoint, EndpointExt, IntoResponse, Middleware, Response};
+use ::poem::{Request, Result, handler};
+use ::poem::{Route, get};
+
+use crate::auth::{self, Access, Auth, AuthRequest, Denied};
+use crate::{Groundwork, Section, W3_CSS, stat, trace};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+impl Groundwork {
+    pub fn register_handlers(&self, route: Route, page_path: &str) -> Route {
+        self.start_sampling();
+        let mut route = route
+            .at(self.endpoint("stats"), {
+                let groundwork = self.clone();
+                get(make_sync(move |_| stats(&groundwork))).with(self.guard())
+            })
+            .at(self.endpoint("stats/history"), {
+                let groundwork = self.clone();
+                get(make_sync(move |_| Json(groundwork.stats_history()))).with(self.guard())
+            });
+        if self.config.is_enabled(Section::Logs) {
+            route = route
+                .at(self.endpoint("logs"), {
+                    let groundwork = self.clone();
+                    get(make_sync(move |request| logs(&groundwork, &request))).with(self.guard())
+                })
+                .at(self.endpoint("logs/stream"), {
+                    let groundwork = self.clone();
+                    get(make_sync(move |request| logs_stream(&groundwork, &request)))
+                        .with(self.stream_guard())
+                })
+                .at(
+                    self.endpoint("log-level"),
+                    get(log_level)
+                        .put(set_log_level)
+                        .with(AddData::new(self.log_level.clone()))
+                        .with(self.guard()),
+                );
+        }
+        if self.config.is_enabled(Section::Calls) {
+            route = route
+                .at(self.endpoint("calls"), {
+                    let groundwork = self.clone();
+                    get(make_sync(move |_| Json(groundwork.calls()))).with(self.guard())
+                })
+                .at(self.endpoint("calls/summary"), {
+                    let groundwork = self.clone();
+                    get(make_sync(move |_| Json(groundwork.calls_summary()))).with(self.guard())
+                })
+                .at(self.endpoint("calls/retained"), {
+                    let groundwork = self.clone();
+                    get(make_sync(move |_| Json(groundwork.calls_retained()))).with(self.guard())
+                })
+                .at(self.endpoint("calls/inflight"), {
+                    let groundwork = self.clone();
+                    get(make_sync(move |_| Json(groundwork.calls_inflight()))).with(self.guard())
+                });
+        }
+        if self.config.is_enabled(Section::Threads) {
+            route = route.at(self.endpoint("threads"), {
+                let groundwork = self.clone();
+                get(make_sync(move |_| threads(&groundwork))).with(self.guard())
+            });
+        }
+        if self.config.is_enabled(Section::Descriptors) {
+            route = route.at(
+                self.endpoint("descriptors"),
+                get(descriptors).with(self.guard()),
+            );
+        }
+        if self.config.is_enabled(Section::Metrics) {
+            route = route.at(self.endpoint("metrics"), {
+                let groundwork = self.clone();
+                get(make_sync(move |_| metrics(&groundwork))).with(self.guard())
+            });
+        }
+        let page = self.page(page_path);
+        route
+            .at(self.endpoint("w3.css"), css.with(self.page_guard()))
+            .at(
+                page_path,
+                get(make_sync(move |_| Html(page.clone()))).with(self.page_guard()),
+            )
+    }
+
+    /// Checks the access rules of the endpoints.
+    fn guard(&self) -> AuthGuard {
+        AuthGuard {
+            auth: self.auth.clone(),
+            access: Access::Endpoint,
+        }
+    }
+
+    /// Checks the access rules of the live log stream, which takes the credentials from the
+    /// query too.
+    fn stream_guard(&self) -> AuthGuard {
+        AuthGuard {
+            auth: self.auth.clone(),
+            access: Access::Stream,
+        }
+    }
+
+    /// Checks the access rules of the page, which asks for the credentials itself.
+    fn page_guard(&self) -> AuthGuard {
+        AuthGuard {
+            auth: self.auth.clone(),
+            access: Access::Page,
+        }
+    }
+}
+
+struct AuthGuard {
+    auth: Arc<Auth>,
+    access: Access,
+}
+
+impl<E: Endpoint> Middleware<E> for AuthGuard {
+    type Output = AuthGuardImpl<E>;
+
+    fn transform(&self, ep: E) -> Self::Output {
+        AuthGuardImpl {
+            endpoint: ep,
+            auth: self.auth.clone(),
+            access: self.access,
+        }
+    }
+}
+
+struct AuthGuardImpl<E> {
+    endpoint: E,
+    auth: Arc<Auth>,
+    access: Access,
+}
+
+impl<E: Endpoint> Endpoint for AuthGuardImpl<E> {
+    type Output = Response;
+
+    async fn call(&self, request: Request) -> Result<Self::Output> {
+        let client = request.remote_addr().as_socket_addr().map(|a| a.ip());
+        let authorization =
+            auth::authorization(self.access, request.headers(), request.uri().query());
+        let auth_request = AuthRequest {
+            method: request.method(),
+            uri: request.original_uri(),
+            headers: request.headers(),
+            client,
+        };
+        let checked = self
+            .auth
+            .check(self.access, &auth_request, authorization.as_deref());
+        match checked {
+            Ok(()) => self
+                .endpoint
+                .call(request)
+                .await
+                .map(IntoResponse::into_response),
+            Err(denied) => Ok(denied_response(denied)),
+        }
+    }
+}
+
+fn denied_response(denied: Denied) -> Response {
+    let status = StatusCode::from_u16(denied.status()).expect("valid status");
+    let mut response = Response::builder().status(status);
+    if let Denied::Unauthorized(challenge) = denied {
+        response = response.header(header::WWW_AUTHENTICATE, challenge);
+    }
+    response.body(status.canonical_reason().unwrap_or_default())
+}
+
+fn stats(groundwork: &Groundwork) -> Result<Json<stat::Stats>> {
+    Ok(Json(groundwork.stats().map_err(error)?))
+}
+
+fn threads(groundwork: &Groundwork) -> Result<Json<Vec<stat::Thread>>> {
+    Ok(Json(groundwork.threads().map_err(error)?))
+}
+
+fn logs(groundwork: &Groundwork, request: &Request) -> Result<Json<Vec<trace::LogRecord>>> {
+    let filter = request.params::<trace::LogFilter>()?;
+    Ok(Json(groundwork.logs(&filter).map_err(error)?))
+}
+
+fn logs_stream(groundwork: &Groundwork, request: &Request) -> Result<Response> {
+    let filter = request.params::<trace::LogFilter>()?;
+    let stream = groundwork.logs_stream(&filter).map_err(error)?.map(|r| {
+        Event::message(serde_json::to_string(&r).unwrap_or_default()).id(r.id.to_string())
+    });
+    // the response, unlike `SSE`, is `Sync` as required by `make_sync`
+    Ok(SSE::new(stream).keep_alive(KEEP_ALIVE).into_response())
+}
+
+#[handler]
+fn log_level(control: Data<&trace::LevelControl>) -> Result<Json<trace::LogLevel>> {
+    Ok(Json(control.get().map_err(error)?))
+}
+
+#[handler]
+fn set_log_level(
+    control: Data<&trace::LevelControl>,
+    Json(level): Json<trace::LogLevel>,
+) -> Result<Json<trace::LogLevel>> {
+    Ok(Json(control.set(&level.directives).map_err(error)?))
+}
+
+#[handler]
+fn descriptors() -> Result<String> {
+    serde_json::to_string(&crate::descriptors::descriptors().map_err(error)?)
+        .map_err(InternalServerError)
+}
+
+fn metrics(groundwork: &Groundwork) -> Result<WithContentType<String>> {
+    Ok(groundwork
+        .metrics()
+        .map_err(error)?
+        .with_content_type(crate::metrics::CONTENT_TYPE))
+}
+
+#[handler]
+fn css() -> WithContentType<&'static str> {
+    W3_CSS.with_content_type("text/css")
+}
+
+fn error(e: crate::Error) -> ::poem::Error {
+    let status = StatusCode::from_u16(e.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    ::poem::Error::from_string(e.to_string(), status)
+}
@@ -0,0 +1,80 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use ::poem::listener::TcpAcceptor;
+use ::poem::{Route, Server};
+use tokio::sync::oneshot;
+
+use crate::Groundwork;
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Handle of the status server started by [`Groundwork::serve`], available with the `poem`
+/// feature.
+///
+/// Dropping the handle leaves the server running until the process exits.
+pub struct StatusServer {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    thread: JoinHandle<std::io::Result<()>>,
+}
+
+impl StatusServer {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting connections, waits for in-flight requests and joins the server thread.
+    pub fn shutdown(self) -> std::io::Result<()> {
+        // the server may already be stopped on error, it is reported by join
+        _ = self.shutdown.send(());
+        self.thread
+            .join()
+            .map_err(|_| std::io::Error::other("status server thread panicked"))?
+    }
+}
+
+impl Groundwork {
+    /// Serves the status page at `/` and the endpoints under the configured prefix on a dedicated
+    /// listener, for services without an HTTP server of their own.
+    ///
+    /// The server runs on its own thread with a single threaded runtime, so it doesn't
+    /// need the application runtime and stays responsive when the application is busy.
+    /// The server is built with Poem, so it requires the `poem` feature, which is enabled by
+    /// default.
+    pub fn serve(&self, addr: impl ToSocketAddrs) -> std::io::Result<StatusServer> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let route = self.register_handlers(Route::new(), "/");
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let thread = std::thread::Builder::new()
+            .name("groundwork".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    Server::new_with_acceptor(TcpAcceptor::from_std(listener)?)
+                        .name("groundwork")
+                        .run_with_graceful_shutdown(
+                            route,
+                            async {
+                                // a dropped handle is not a shutdown request
+                                if signal.await.is_err() {
+                                    std::future::pending::<()>().await;
+                                }
+                            },
+                            Some(SHUTDOWN_TIMEOUT),
+                        )
+                        .await
+                })
+            })?;
+        Ok(StatusServer {
+            local_addr,
+            shutdown,
+            thread,
+        })
+    }
+}
@@ -1,13 +1,23 @@
-use std::sync::Arc;
-
-use poem::error::InternalServerError;
-use poem::web::Data;
-use poem::{Error, Result, handler, http::StatusCode};
 use serde::Serialize;
 
+use crate::Error;
+
+#[cfg(target_os = "linux")]
+mod cgroup;
+mod history;
+mod threads;
+pub(crate) use history::History;
+pub use history::{
+    CPU_USAGE_WINDOWS, CpuUsage, IoRates, LONG_TERM_INTERVAL, LONG_TERM_LEN, RECENT_INTERVAL,
+    RECENT_LEN, Sample, StatsHistory,
+};
+pub use threads::{Thread, threads};
+
 pub struct StatsData {
     name: String,
     usage_time_to_us: f64,
+    #[cfg(target_os = "linux")]
+    cgroup: Option<cgroup::CGroup>,
 }
 
 impl StatsData {
@@ -15,7 +25,19 @@ impl StatsData {
         Self {
             name: name.to_string(),
             usage_time_to_us: usage_time_to_us(),
+            #[cfg(target_os = "linux")]
+            cgroup: cgroup::CGroup::myself().ok(),
+        }
+    }
+
+    /// CPUs the process can use, limited by the CPU affinity and the cgroup CPU quota.
+    fn cpu_cores(&self) -> f64 {
+        let cores = std::thread::available_parallelism().map_or(1, std::num::NonZero::get) as f64;
+        #[cfg(target_os = "linux")]
+        if let Some(limit) = self.cgroup.as_ref().and_then(cgroup::CGroup::cpu_limit) {
+            return cores.min(limit);
         }
+        cores
     }
 }
 
@@ -36,87 +58,251 @@ fn usage_time_to_us() -> f64 {
     1e6 / ticks_per_second as f64
 }
 
-#[handler]
-pub fn stats(data: Data<&Arc<StatsData>>) -> Result<String> {
+/// Snapshot of the current process resource usage.
+pub fn stats(data: &StatsData) -> Result<Stats, Error> {
+    stats_with(data, &sample(data, crate::call::current_timestamp_ms())?)
+}
+
+/// [`Stats`] with the fields of `sample` taken just before.
+pub(crate) fn stats_with(data: &StatsData, sample: &Sample) -> Result<Stats, Error> {
+    let hostname = hostname::get()?
+        .into_string()
+        .map_err(|e| Error::Hostname(e.to_string_lossy().into_owned()))?;
+    #[cfg(target_os = "linux")]
+    let (mem_rss_peak, start_time_ms, cgroup) = {
+        use procfs::WithCurrentSystemInfo;
+        let process = procfs::process::Process::myself()?;
+        (
+            process.status()?.vmhwm.unwrap() * 1024,
+            process.stat()?.starttime().get()?.timestamp_millis() as u64,
+            data.cgroup.as_ref().map(cgroup::CGroup::stats),
+        )
+    };
+    #[cfg(target_os = "macos")]
+    let (mem_rss_peak, start_time_ms, cgroup) = {
+        let info = task_info()?;
+        let mut rusage;
+        unsafe {
+            rusage = std::mem::zeroed();
+            if libc::getrusage(libc::RUSAGE_SELF, &mut rusage) != libc::EXIT_SUCCESS {
+                panic!();
+            }
+        }
+        (
+            rusage.ru_maxrss as u64,
+            info.pbsd.pbi_start_tvsec * 1000 + info.pbsd.pbi_start_tvusec / 1000,
+            None,
+        )
+    };
+    Ok(Stats {
+        name: data.name.clone(),
+        hostname,
+        mem_rss: sample.mem_rss,
+        mem_rss_peak,
+        mem_virtual: sample.mem_virtual,
+        mem_allocated_bytes: sample.mem_allocated_bytes,
+        allocations: sample.allocations,
+        fd_count: sample.fd_count,
+        threads_count: sample.threads_count,
+        user_time_us: sample.user_time_us,
+        system_time_us: sample.system_time_us,
+        start_time_ms,
+        cpu_cores: data.cpu_cores(),
+        cpu_usage: Vec::new(),
+        io: sample.io,
+        io_rates: Vec::new(),
+        cgroup,
+    })
+}
+
+/// Reads only the fields of [`Sample`], without the hostname, the start time and the cgroup
+/// files of [`stats`], for the background sampling.
+pub fn sample(data: &StatsData, timestamp_ms: u64) -> Result<Sample, Error> {
     let allocator_metrics = alloc_metrics::global_metrics();
     let mem_allocated_bytes = allocator_metrics.allocated_bytes as u64;
     let allocations = allocator_metrics.allocations as u64;
-    let name = data.name.clone();
-    let hostname = hostname::get()
-        .map_err(InternalServerError)?
-        .into_string()
-        .map_err(|e| Error::from_string(e.to_string_lossy(), StatusCode::INTERNAL_SERVER_ERROR))?;
+    let instant = std::time::Instant::now();
 
-    let stats = {
+    Ok({
         #[cfg(target_os = "linux")]
         {
-            use procfs::WithCurrentSystemInfo;
-            let map_err = |e: procfs::ProcError| {
-                Error::from_string(e.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-            };
-            let process = procfs::process::Process::myself().map_err(map_err)?;
-            let status = process.status().map_err(map_err)?;
-            let stat = process.stat().map_err(map_err)?;
-            Stats {
-                name,
-                hostname,
-                mem_allocated_bytes,
-                allocations,
+            let process = procfs::process::Process::myself()?;
+            let status = process.status()?;
+            let stat = process.stat()?;
+            Sample {
+                timestamp_ms,
+                instant,
                 mem_rss: status.vmrss.unwrap() * 1024,
-                mem_rss_peak: status.vmhwm.unwrap() * 1024,
                 mem_virtual: status.vmsize.unwrap() * 1024,
-                fd_count: process.fd_count().map_err(map_err)? as u64,
+                mem_allocated_bytes,
+                allocations,
+                fd_count: process.fd_count()? as u64,
                 threads_count: status.threads,
                 user_time_us: (stat.utime as f64 * data.usage_time_to_us) as u64,
                 system_time_us: (stat.stime as f64 * data.usage_time_to_us) as u64,
-                start_time_ms: stat.starttime().get().map_err(map_err)?.timestamp_millis() as u64,
+                // hidden from other users, e.g. in some sandboxes
+                io: process.io().ok().map(|io| IoStats {
+                    rchar: io.rchar,
+                    wchar: io.wchar,
+                    syscr: io.syscr,
+                    syscw: io.syscw,
+                    read_bytes: io.read_bytes,
+                    write_bytes: io.write_bytes,
+                    cancelled_write_bytes: io.cancelled_write_bytes,
+                }),
             }
         }
         #[cfg(target_os = "macos")]
         {
-            let pid = std::process::id();
-            let info = libproc::proc_pid::pidinfo::<libproc::task_info::TaskAllInfo>(pid as i32, 1)
-                .map_err(|e| Error::from_string(e, StatusCode::INTERNAL_SERVER_ERROR))?;
-            let mut rusage;
-            unsafe {
-                rusage = std::mem::zeroed();
-                if libc::getrusage(libc::RUSAGE_SELF, &mut rusage) != libc::EXIT_SUCCESS {
-                    panic!();
-                }
-            }
-            Stats {
-                name,
-                hostname,
-                mem_allocated_bytes,
-                allocations,
+            let info = task_info()?;
+            Sample {
+                timestamp_ms,
+                instant,
                 mem_rss: info.ptinfo.pti_resident_size,
-                mem_rss_peak: rusage.ru_maxrss as u64,
                 mem_virtual: info.ptinfo.pti_virtual_size,
+                mem_allocated_bytes,
+                allocations,
                 fd_count: info.pbsd.pbi_nfiles as u64,
                 threads_count: info.ptinfo.pti_threadnum as u64,
                 user_time_us: (info.ptinfo.pti_total_user as f64 * data.usage_time_to_us) as u64,
                 system_time_us: (info.ptinfo.pti_total_system as f64 * data.usage_time_to_us)
                     as u64,
-                start_time_ms: info.pbsd.pbi_start_tvsec * 1000 + info.pbsd.pbi_start_tvusec / 1000,
+                io: None,
             }
         }
-    };
-    serde_json::to_string(&stats).map_err(InternalServerError)
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn task_info() -> Result<libproc::task_info::TaskAllInfo, Error> {
+    let pid = std::process::id();
+    libproc::proc_pid::pidinfo::<libproc::task_info::TaskAllInfo>(pid as i32, 1)
+        .map_err(Error::Process)
 }
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
-struct Stats {
-    name: String,
-    hostname: String,
-    mem_rss: u64,
-    mem_rss_peak: u64,
-    mem_virtual: u64,
-    mem_allocated_bytes: u64,
-    allocations: u64,
-    fd_count: u64,
-    threads_count: u64,
-    user_time_us: u64,
-    system_time_us: u64,
-    start_time_ms: u64,
+pub struct Stats {
+    pub name: String,
+    pub hostname: String,
+    pub mem_rss: u64,
+    pub mem_rss_peak: u64,
+    pub mem_virtual: u64,
+    pub mem_allocated_bytes: u64,
+    pub allocations: u64,
+    pub fd_count: u64,
+    pub threads_count: u64,
+    pub user_time_us: u64,
+    pub system_time_us: u64,
+    pub start_time_ms: u64,
+    /// CPUs available to the process, fractional under a cgroup quota.
+    pub cpu_cores: f64,
+    /// CPU utilization over [`CPU_USAGE_WINDOWS`], computed from the sampled history by
+    /// [`crate::Groundwork::stats`], windows without enough history are left out.
+    pub cpu_usage: Vec<CpuUsage>,
+    /// Linux only.
+    pub io: Option<IoStats>,
+    /// I/O per second over the [`CPU_USAGE_WINDOWS`], computed like `cpu_usage`.
+    pub io_rates: Vec<IoRates>,
+    /// Limits and usage of the control group of the process, Linux only.
+    pub cgroup: Option<CGroupStats>,
+}
+
+/// I/O counters of the process from `/proc/self/io`.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IoStats {
+    /// Bytes passed to `read` and similar calls, including reads from the page cache,
+    /// sockets and pipes.
+    pub rchar: u64,
+    /// Bytes passed to `write` and similar calls.
+    pub wchar: u64,
+    /// Read calls.
+    pub syscr: u64,
+    /// Write calls.
+    pub syscw: u64,
+    /// Bytes fetched from the storage layer.
+    pub read_bytes: u64,
+    /// Bytes sent to the storage layer.
+    pub write_bytes: u64,
+    /// Written bytes which were not stored because the file was truncated.
+    pub cancelled_write_bytes: u64,
+}
+
+/// Control group of the process, from the v2 hierarchy or from the v1 hierarchies of the
+/// controllers.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CGroupStats {
+    pub memory: Option<CGroupMemory>,
+    pub cpu: Option<CGroupCpu>,
+    /// Pressure stall information, v2 only.
+    pub memory_pressure: Option<Pressure>,
+    pub cpu_pressure: Option<Pressure>,
+    pub io_pressure: Option<Pressure>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CGroupMemory {
+    /// Memory used by the group, including the page cache.
+    pub current: u64,
+    /// The lowest limit of the group and its parents, `None` without a limit.
+    pub max: Option<u64>,
+    /// Times the limit was reached and the reclaim failed, v2 only.
+    pub oom: Option<u64>,
+    /// Processes killed by the OOM killer.
+    pub oom_kill: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CGroupCpu {
+    /// CPUs allowed by the quota of the group and its parents, `None` without a quota.
+    pub quota_cores: Option<f64>,
+    /// Enforcement periods elapsed.
+    pub periods: u64,
+    /// Periods in which the group used up its quota.
+    pub throttled_periods: u64,
+    pub throttled_us: u64,
+}
+
+/// Share of time in which some or all tasks of the group were stalled on a resource.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Pressure {
+    pub some: PressureAverages,
+    /// Not reported for CPU by older kernels.
+    pub full: Option<PressureAverages>,
+}
+
+/// Percent of time stalled averaged over 10 seconds, 1 and 5 minutes, and the total stall time.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PressureAverages {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    pub total_us: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_has_the_stats_fields() {
+        let data = StatsData::new("test");
+        let sample = sample(&data, 1234).unwrap();
+        assert_eq!(sample.timestamp_ms, 1234);
+        assert!(sample.mem_rss > 0);
+        assert!(sample.threads_count > 0);
+        assert!(sample.fd_count > 0);
+
+        let stats = stats_with(&data, &sample).unwrap();
+        assert_eq!(stats.name, "test");
+        assert_eq!(stats.mem_rss, sample.mem_rss);
+        assert_eq!(stats.user_time_us, sample.user_time_us);
+        assert!(stats.mem_rss_peak >= stats.mem_rss);
+    }
 }
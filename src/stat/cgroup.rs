@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use procfs::process::{MountInfo, Process};
+
+use super::{CGroupCpu, CGroupMemory, CGroupStats, Pressure, PressureAverages};
+use crate::Error;
+
+/// v1 reports a page aligned `i64::MAX` when there is no memory limit.
+const V1_NO_MEMORY_LIMIT: u64 = 1 << 62;
+
+/// Directories of the control groups of the process: the v2 group, and the v1 groups by
+/// controller when v1 hierarchies are mounted.
+#[derive(Debug, Default)]
+pub(crate) struct CGroup {
+    v2: Option<Group>,
+    v1: HashMap<String, Group>,
+}
+
+#[derive(Debug)]
+struct Group {
+    dir: PathBuf,
+    mount_point: PathBuf,
+}
+
+impl Group {
+    fn new(mount: &MountInfo, pathname: &str) -> Self {
+        // the pathname is relative to the root of the hierarchy, the mount can expose a subtree
+        let dir = pathname
+            .strip_prefix(mount.root.as_str())
+            .map(|relative| mount.mount_point.join(relative.trim_start_matches('/')))
+            .filter(|dir| dir.is_dir())
+            .unwrap_or_else(|| mount.mount_point.clone());
+        Self {
+            dir,
+            mount_point: mount.mount_point.clone(),
+        }
+    }
+
+    /// The group directory and its parents up to the mount point, limits of the parents
+    /// apply too.
+    fn dirs(&self) -> impl Iterator<Item = &Path> {
+        self.dir
+            .ancestors()
+            .take_while(|dir| dir.starts_with(&self.mount_point))
+    }
+}
+
+impl CGroup {
+    pub(crate) fn myself() -> Result<Self, Error> {
+        let process = Process::myself()?;
+        let mounts = process.mountinfo()?;
+        let mut cgroup = Self::default();
+        for group in process.cgroups()?.0 {
+            if group.hierarchy == 0 {
+                cgroup.v2 = mounts
+                    .iter()
+                    .find(|m| m.fs_type == "cgroup2")
+                    .map(|m| Group::new(m, &group.pathname));
+            } else {
+                for controller in group.controllers {
+                    let mount = mounts.iter().find(|m| {
+                        m.fs_type == "cgroup" && m.super_options.contains_key(&controller)
+                    });
+                    if let Some(mount) = mount {
+                        cgroup
+                            .v1
+                            .insert(controller, Group::new(mount, &group.pathname));
+                    }
+                }
+            }
+        }
+        Ok(cgroup)
+    }
+
+    /// CPUs allowed by the CPU quota, `None` without a quota.
+    pub(crate) fn cpu_limit(&self) -> Option<f64> {
+        if let Some(v2) = self.v2_with("cpu") {
+            v2.dirs()
+                .filter_map(|dir| cpu_max(&read(&dir.join("cpu.max"))?))
+                .reduce(f64::min)
+        } else {
+            self.v1
+                .get("cpu")?
+                .dirs()
+                .filter_map(|dir| {
+                    cfs_quota(
+                        &read(&dir.join("cpu.cfs_quota_us"))?,
+                        &read(&dir.join("cpu.cfs_period_us"))?,
+                    )
+                })
+                .reduce(f64::min)
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CGroupStats {
+        CGroupStats {
+            memory: self.memory(),
+            cpu: self.cpu(),
+            memory_pressure: self.pressure("memory"),
+            cpu_pressure: self.pressure("cpu"),
+            io_pressure: self.pressure("io"),
+        }
+    }
+
+    fn memory(&self) -> Option<CGroupMemory> {
+        if let Some(v2) = self.v2_with("memory") {
+            let events = read(&v2.dir.join("memory.events"));
+            let events = keyed(events.as_deref().unwrap_or_default());
+            Some(CGroupMemory {
+                current: read(&v2.dir.join("memory.current"))?.parse().ok()?,
+                max: v2
+                    .dirs()
+                    .filter_map(|dir| memory_max(&read(&dir.join("memory.max"))?))
+                    .min(),
+                oom: events.get("oom").copied(),
+                oom_kill: events.get("oom_kill").copied(),
+            })
+        } else {
+            let v1 = self.v1.get("memory")?;
+            let oom_control = read(&v1.dir.join("memory.oom_control"));
+            Some(CGroupMemory {
+                current: read(&v1.dir.join("memory.usage_in_bytes"))?.parse().ok()?,
+                max: v1
+                    .dirs()
+                    .filter_map(|dir| v1_memory_limit(&read(&dir.join("memory.limit_in_bytes"))?))
+                    .min(),
+                // v1 counts only the kills
+                oom: None,
+                oom_kill: keyed(oom_control.as_deref().unwrap_or_default())
+                    .get("oom_kill")
+                    .copied(),
+            })
+        }
+    }
+
+    fn cpu(&self) -> Option<CGroupCpu> {
+        let mut cpu = if let Some(v2) = self.v2_with("cpu") {
+            v2_cpu_stat(&read(&v2.dir.join("cpu.stat"))?)
+        } else {
+            v1_cpu_stat(&read(&self.v1.get("cpu")?.dir.join("cpu.stat"))?)
+        };
+        cpu.quota_cores = self.cpu_limit();
+        Some(cpu)
+    }
+
+    /// Pressure stall information of the v2 group, there is none for v1.
+    fn pressure(&self, resource: &str) -> Option<Pressure> {
+        pressure(&read(
+            &self.v2.as_ref()?.dir.join(format!("{resource}.pressure")),
+        )?)
+    }
+
+    /// The v2 group if the controller is enabled in it, in hybrid setups controllers are
+    /// bound to v1 hierarchies.
+    fn v2_with(&self, controller: &str) -> Option<&Group> {
+        self.v2.as_ref().filter(|v2| {
+            read(&v2.dir.join("cgroup.controllers"))
+                .is_some_and(|c| c.split_whitespace().any(|c| c == controller))
+        })
+    }
+}
+
+/// CPUs of a v2 `cpu.max`: `$MAX $PERIOD`, `max` means no limit.
+fn cpu_max(content: &str) -> Option<f64> {
+    let (max, period) = content.split_once(' ')?;
+    Some(max.parse::<f64>().ok()? / period.trim().parse::<f64>().ok()?)
+}
+
+/// CPUs of the v1 `cpu.cfs_quota_us` and `cpu.cfs_period_us`, -1 means no limit.
+fn cfs_quota(quota: &str, period: &str) -> Option<f64> {
+    let quota = quota.parse::<i64>().ok()?;
+    let period = period.parse::<i64>().ok()?;
+    (quota > 0 && period > 0).then(|| quota as f64 / period as f64)
+}
+
+/// A v2 `memory.max`, `max` means no limit.
+fn memory_max(content: &str) -> Option<u64> {
+    content.parse().ok()
+}
+
+/// A v1 `memory.limit_in_bytes`, a huge value means no limit.
+fn v1_memory_limit(content: &str) -> Option<u64> {
+    content.parse().ok().filter(|max| *max < V1_NO_MEMORY_LIMIT)
+}
+
+/// Throttling of a v2 `cpu.stat`, without the quota.
+fn v2_cpu_stat(content: &str) -> CGroupCpu {
+    let stat = keyed(content);
+    cpu_stat(&stat, stat.get("throttled_usec").copied())
+}
+
+/// Throttling of a v1 `cpu.stat`, without the quota.
+fn v1_cpu_stat(content: &str) -> CGroupCpu {
+    let stat = keyed(content);
+    // nanoseconds in v1
+    cpu_stat(&stat, stat.get("throttled_time").map(|ns| ns / 1000))
+}
+
+fn cpu_stat(stat: &HashMap<&str, u64>, throttled_us: Option<u64>) -> CGroupCpu {
+    CGroupCpu {
+        quota_cores: None,
+        periods: stat.get("nr_periods").copied().unwrap_or_default(),
+        throttled_periods: stat.get("nr_throttled").copied().unwrap_or_default(),
+        throttled_us: throttled_us.unwrap_or_default(),
+    }
+}
+
+/// `some avg10=0.00 avg60=0.00 avg300=0.00 total=0`, then the same for `full`.
+fn pressure(content: &str) -> Option<Pressure> {
+    let mut pressure = Pressure {
+        some: PressureAverages::default(),
+        full: None,
+    };
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next();
+        let mut averages = PressureAverages::default();
+        for (name, value) in fields.filter_map(|f| f.split_once('=')) {
+            match name {
+                "avg10" => averages.avg10 = value.parse().ok()?,
+                "avg60" => averages.avg60 = value.parse().ok()?,
+                "avg300" => averages.avg300 = value.parse().ok()?,
+                "total" => averages.total_us = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        match kind {
+            Some("some") => pressure.some = averages,
+            Some("full") => pressure.full = Some(averages),
+            _ => {}
+        }
+    }
+    Some(pressure)
+}
+
+/// Values of `name value` lines, as in `memory.events` and `cpu.stat`.
+fn keyed(content: &str) -> HashMap<&str, u64> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(' ')?;
+            Some((name, value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+fn read(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_values() {
+        let events = keyed("low 0\nhigh 12\noom 3\noom_kill 1\nbroken\nnot_a_number x\n");
+        assert_eq!(events.get("high"), Some(&12));
+        assert_eq!(events.get("oom"), Some(&3));
+        assert_eq!(events.get("oom_kill"), Some(&1));
+        assert_eq!(events.get("broken"), None);
+        assert_eq!(events.get("not_a_number"), None);
+    }
+
+    #[test]
+    fn cpu_quota() {
+        assert_eq!(cpu_max("max 100000"), None);
+        assert_eq!(cpu_max("150000 100000"), Some(1.5));
+        assert_eq!(cpu_max("garbage"), None);
+        assert_eq!(cfs_quota("-1", "100000"), None);
+        assert_eq!(cfs_quota("50000", "100000"), Some(0.5));
+        assert_eq!(cfs_quota("50000", "0"), None);
+    }
+
+    #[test]
+    fn memory_limit() {
+        assert_eq!(memory_max("max"), None);
+        assert_eq!(memory_max("536870912"), Some(536870912));
+        assert_eq!(v1_memory_limit("9223372036854771712"), None);
+        assert_eq!(v1_memory_limit("536870912"), Some(536870912));
+    }
+
+    #[test]
+    fn cpu_throttling() {
+        let v2 = "usage_usec 1000\nuser_usec 600\nsystem_usec 400\n\
+                  nr_periods 20\nnr_throttled 5\nthrottled_usec 7000\n";
+        let v1 = "nr_periods 20\nnr_throttled 5\nthrottled_time 7000000\n";
+        let expected = CGroupCpu {
+            quota_cores: None,
+            periods: 20,
+            throttled_periods: 5,
+            throttled_us: 7000,
+        };
+        assert_eq!(v2_cpu_stat(v2), expected);
+        assert_eq!(v1_cpu_stat(v1), expected);
+        // without a CPU controller quota there are no periods
+        assert_eq!(
+            v2_cpu_stat("usage_usec 1000\n"),
+            CGroupCpu {
+                periods: 0,
+                throttled_periods: 0,
+                throttled_us: 0,
+                ..expected
+            }
+        );
+    }
+
+    #[test]
+    fn pressure_lines() {
+        let memory = "some avg10=1.50 avg60=0.25 avg300=0.00 total=12345\n\
+                      full avg10=0.50 avg60=0.00 avg300=0.00 total=678\n";
+        assert_eq!(
+            pressure(memory),
+            Some(Pressure {
+                some: PressureAverages {
+                    avg10: 1.5,
+                    avg60: 0.25,
+                    avg300: 0.0,
+                    total_us: 12345,
+                },
+                full: Some(PressureAverages {
+                    avg10: 0.5,
+                    total_us: 678,
+                    ..Default::default()
+                }),
+            })
+        );
+        // older kernels report only `some` for CPU
+        let cpu = pressure("some avg10=0.00 avg60=0.00 avg300=0.00 total=10\n").unwrap();
+        assert_eq!(cpu.some.total_us, 10);
+        assert_eq!(cpu.full, None);
+        assert_eq!(pressure("some avg10=x"), None);
+    }
+
+    /// Cgroup files under a temporary mount point, removed on drop.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir()
+                .join(format!("groundwork-cgroup-{name}-{}", std::process::id()));
+            _ = std::fs::remove_dir_all(&root);
+            Self(root)
+        }
+
+        fn write(&self, path: &str, content: &str) -> &Self {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+            self
+        }
+
+        fn group(&self, dir: &str) -> Group {
+            Group {
+                dir: self.0.join(dir),
+                mount_point: self.0.clone(),
+            }
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn v2_limits_are_the_lowest_of_the_ancestors() {
+        let fixture = Fixture::new("v2");
+        fixture
+            .write("cgroup.controllers", "cpu memory io")
+            .write("app/cgroup.controllers", "cpu memory")
+            .write("app/cpu.max", "200000 100000\n")
+            .write("app/memory.max", "1073741824\n")
+            .write("app/service/cgroup.controllers", "cpu memory")
+            .write("app/service/cpu.max", "max 100000\n")
+            .write("app/service/memory.max", "max\n")
+            .write("app/service/memory.current", "4096\n")
+            .write("app/service/memory.events", "low 0\noom 2\noom_kill 1\n")
+            .write("app/service/cpu.stat", "nr_periods 10\nnr_throttled 1\n")
+            .write(
+                "app/service/io.pressure",
+                "some avg10=0.00 avg60=0.00 avg300=0.00 total=5\n",
+            );
+        let cgroup = CGroup {
+            v2: Some(fixture.group("app/service")),
+            v1: HashMap::new(),
+        };
+        assert_eq!(cgroup.cpu_limit(), Some(2.0));
+        let stats = cgroup.stats();
+        assert_eq!(
+            stats.memory,
+            Some(CGroupMemory {
+                current: 4096,
+                max: Some(1073741824),
+                oom: Some(2),
+                oom_kill: Some(1),
+            })
+        );
+        assert_eq!(stats.cpu.unwrap().quota_cores, Some(2.0));
+        assert_eq!(stats.io_pressure.unwrap().some.total_us, 5);
+        assert_eq!(stats.memory_pressure, None);
+    }
+
+    #[test]
+    fn v1_limits_are_the_lowest_of_the_ancestors() {
+        let fixture = Fixture::new("v1");
+        fixture
+            .write("cpu/cpu.cfs_quota_us", "-1\n")
+            .write("cpu/cpu.cfs_period_us", "100000\n")
+            .write("cpu/app/cpu.cfs_quota_us", "50000\n")
+            .write("cpu/app/cpu.cfs_period_us", "100000\n")
+            .write("cpu/app/service/cpu.cfs_quota_us", "150000\n")
+            .write("cpu/app/service/cpu.cfs_period_us", "100000\n")
+            .write("cpu/app/service/cpu.stat", "nr_periods 4\n")
+            .write("memory/memory.limit_in_bytes", "9223372036854771712\n")
+            .write("memory/app/memory.limit_in_bytes", "9223372036854771712\n")
+            .write("memory/app/memory.usage_in_bytes", "8192\n")
+            .write(
+                "memory/app/memory.oom_control",
+                "oom_kill_disable 0\nunder_oom 0\noom_kill 3\n",
+            );
+        let cpu = Group {
+            dir: fixture.0.join("cpu/app/service"),
+            mount_point: fixture.0.join("cpu"),
+        };
+        let memory = Group {
+            dir: fixture.0.join("memory/app"),
+            mount_point: fixture.0.join("memory"),
+        };
+        let cgroup = CGroup {
+            v2: None,
+            v1: HashMap::from([("cpu".to_string(), cpu), ("memory".to_string(), memory)]),
+        };
+        assert_eq!(cgroup.cpu_limit(), Some(0.5));
+        let stats = cgroup.stats();
+        assert_eq!(
+            stats.memory,
+            Some(CGroupMemory {
+                current: 8192,
+                max: None,
+                oom: None,
+                oom_kill: Some(3),
+            })
+        );
+        assert_eq!(stats.cpu.unwrap().periods, 4);
+        assert_eq!(stats.cpu_pressure, None);
+    }
+}
@@ -0,0 +1,332 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::threads::{ThreadTimes, thread_times};
+use super::{IoStats, StatsData};
+
+/// Interval of the recent samples.
+pub const RECENT_INTERVAL: Duration = Duration::from_secs(1);
+/// 10 minutes of the recent samples.
+pub const RECENT_LEN: usize = 600;
+/// Interval of the long term samples.
+pub const LONG_TERM_INTERVAL: Duration = Duration::from_secs(60);
+/// 24 hours of the long term samples.
+pub const LONG_TERM_LEN: usize = 1440;
+/// Windows of [`CpuUsage`] and [`IoRates`], covered by the recent samples.
+pub const CPU_USAGE_WINDOWS: [Duration; 4] = [
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(300),
+];
+
+/// Numeric part of [`super::Stats`] taken at a point in time.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Sample {
+    /// Wall clock time, for display.
+    pub timestamp_ms: u64,
+    /// Monotonic time the rates are computed with, the wall clock can be stepped.
+    #[serde(skip)]
+    pub(crate) instant: Instant,
+    pub mem_rss: u64,
+    pub mem_virtual: u64,
+    pub mem_allocated_bytes: u64,
+    pub allocations: u64,
+    pub fd_count: u64,
+    pub threads_count: u64,
+    pub user_time_us: u64,
+    pub system_time_us: u64,
+    pub io: Option<IoStats>,
+}
+
+/// User and system CPU time used over a window, in percent of the elapsed time.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CpuUsage {
+    pub window_secs: u64,
+    /// 100% per fully used core.
+    pub percent: f64,
+    /// `percent` divided by [`super::Stats::cpu_cores`], 100% when all available CPU is used.
+    pub normalized_percent: f64,
+}
+
+/// [`IoStats`] counters per second over a window.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IoRates {
+    pub window_secs: u64,
+    pub rchar: f64,
+    pub wchar: f64,
+    pub syscr: f64,
+    pub syscw: f64,
+    pub read_bytes: f64,
+    pub write_bytes: f64,
+    pub cancelled_write_bytes: f64,
+}
+
+impl IoRates {
+    fn new(window: Duration, current: &IoStats, past: &IoStats, elapsed_secs: f64) -> Self {
+        let rate = |current: u64, past: u64| current.saturating_sub(past) as f64 / elapsed_secs;
+        Self {
+            window_secs: window.as_secs(),
+            rchar: rate(current.rchar, past.rchar),
+            wchar: rate(current.wchar, past.wchar),
+            syscr: rate(current.syscr, past.syscr),
+            syscw: rate(current.syscw, past.syscw),
+            read_bytes: rate(current.read_bytes, past.read_bytes),
+            write_bytes: rate(current.write_bytes, past.write_bytes),
+            cancelled_write_bytes: rate(current.cancelled_write_bytes, past.cancelled_write_bytes),
+        }
+    }
+}
+
+/// Samples oldest first.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsHistory {
+    /// Taken every [`RECENT_INTERVAL`].
+    pub recent: Vec<Sample>,
+    /// Taken every [`LONG_TERM_INTERVAL`].
+    pub long_term: Vec<Sample>,
+}
+
+/// Bounded rings of the samples.
+#[derive(Default)]
+pub struct History {
+    recent: VecDeque<Sample>,
+    long_term: VecDeque<Sample>,
+    /// The two latest thread samples, when threads are sampled.
+    thread_times: VecDeque<ThreadTimes>,
+}
+
+impl History {
+    pub fn add(&mut self, sample: Sample) {
+        let long_term_ms = LONG_TERM_INTERVAL.as_millis() as u64;
+        let due = self.long_term.back().is_none_or(|last| {
+            sample.timestamp_ms / long_term_ms > last.timestamp_ms / long_term_ms
+        });
+        if due {
+            push(&mut self.long_term, sample.clone(), LONG_TERM_LEN);
+        }
+        push(&mut self.recent, sample, RECENT_LEN);
+    }
+
+    pub fn history(&self) -> StatsHistory {
+        StatsHistory {
+            recent: self.recent.iter().cloned().collect(),
+            long_term: self.long_term.iter().cloned().collect(),
+        }
+    }
+
+    pub(crate) fn add_thread_times(&mut self, times: ThreadTimes) {
+        push(&mut self.thread_times, times, 2);
+    }
+
+    /// CPU usage of the threads in percent between the two latest thread samples.
+    pub(crate) fn thread_usage(&self) -> HashMap<i32, f64> {
+        match (self.thread_times.front(), self.thread_times.back()) {
+            (Some(previous), Some(latest)) => latest.usage_since(previous),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// Usage between the samples taken a window ago and `current`.
+    pub(crate) fn cpu_usage(&self, current: &Sample, cores: f64) -> Vec<CpuUsage> {
+        CPU_USAGE_WINDOWS
+            .iter()
+            .filter_map(|window| {
+                let past = self.past(current, *window)?;
+                let elapsed_us = (current.instant - past.instant).as_micros() as u64;
+                let used_us = (current.user_time_us + current.system_time_us)
+                    .saturating_sub(past.user_time_us + past.system_time_us);
+                let percent = used_us as f64 * 100.0 / elapsed_us as f64;
+                Some(CpuUsage {
+                    window_secs: window.as_secs(),
+                    percent,
+                    normalized_percent: percent / cores,
+                })
+            })
+            .collect()
+    }
+
+    /// Rates between the samples taken a window ago and `current`.
+    pub(crate) fn io_rates(&self, current: &Sample) -> Vec<IoRates> {
+        let Some(current_io) = &current.io else {
+            return Vec::new();
+        };
+        CPU_USAGE_WINDOWS
+            .iter()
+            .filter_map(|window| {
+                let past = self.past(current, *window)?;
+                let elapsed_secs = (current.instant - past.instant).as_secs_f64();
+                Some(IoRates::new(
+                    *window,
+                    current_io,
+                    past.io.as_ref()?,
+                    elapsed_secs,
+                ))
+            })
+            .collect()
+    }
+
+    /// The latest sample taken at least `window` before `current`.
+    fn past(&self, current: &Sample, window: Duration) -> Option<&Sample> {
+        self.recent
+            .iter()
+            .rev()
+            .find(|s| s.instant + window <= current.instant)
+    }
+
+    /// Starts a thread which adds a sample every [`RECENT_INTERVAL`] until the history is dropped,
+    /// with CPU times of every thread if `threads` is set.
+    pub(crate) fn start_sampling(
+        history: &Arc<Mutex<History>>,
+        data: Arc<StatsData>,
+        threads: bool,
+    ) {
+        let history = Arc::downgrade(history);
+        let spawned = std::thread::Builder::new()
+            .name("groundwork-stats".to_string())
+            .spawn(move || run_sampling(history, data, threads));
+        if let Err(e) = spawned {
+            tracing::warn!("cannot start stats sampling: {e}");
+        }
+    }
+}
+
+fn run_sampling(history: Weak<Mutex<History>>, data: Arc<StatsData>, threads: bool) {
+    loop {
+        let Some(history) = history.upgrade() else {
+            return;
+        };
+        let timestamp_ms = crate::call::current_timestamp_ms();
+        // a failed sample leaves a gap
+        if let Ok(sample) = super::sample(&data, timestamp_ms) {
+            history.lock().expect("can lock history").add(sample);
+        }
+        if threads && let Ok(times) = thread_times(&data) {
+            history
+                .lock()
+                .expect("can lock history")
+                .add_thread_times(times);
+        }
+        drop(history);
+        std::thread::sleep(RECENT_INTERVAL);
+    }
+}
+
+fn push<T>(samples: &mut VecDeque<T>, sample: T, len: usize) {
+    if samples.len() == len {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample taken `secs` after `start` with the total CPU time and bytes read so far.
+    fn sample(start: Instant, secs: u64, cpu_ms: u64, read_bytes: u64) -> Sample {
+        Sample {
+            timestamp_ms: 1_700_000_000_000 + secs * 1000,
+            instant: start + Duration::from_secs(secs),
+            mem_rss: 0,
+            mem_virtual: 0,
+            mem_allocated_bytes: 0,
+            allocations: 0,
+            fd_count: 0,
+            threads_count: 0,
+            user_time_us: cpu_ms * 1000 / 2,
+            system_time_us: cpu_ms * 1000 / 2,
+            io: Some(IoStats {
+                read_bytes,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn percents(usage: &[CpuUsage]) -> Vec<(u64, f64, f64)> {
+        usage
+            .iter()
+            .map(|u| (u.window_secs, u.percent, u.normalized_percent))
+            .collect()
+    }
+
+    #[test]
+    fn cpu_usage_over_the_windows() {
+        let start = Instant::now();
+        let mut history = History::default();
+        // half a core for a minute, then a full core
+        for secs in 0..=60 {
+            history.add(sample(start, secs, secs * 500, 0));
+        }
+        for secs in 61..=70 {
+            history.add(sample(start, secs, 30_000 + (secs - 60) * 1000, 0));
+        }
+        let current = history.recent.back().unwrap().clone();
+        assert_eq!(
+            percents(&history.cpu_usage(&current, 4.0)),
+            [
+                (1, 100.0, 25.0),
+                (10, 100.0, 25.0),
+                (60, 58.333333333333336, 14.583333333333334)
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_without_enough_history_are_left_out() {
+        let start = Instant::now();
+        let mut history = History::default();
+        history.add(sample(start, 0, 0, 0));
+        let current = sample(start, 0, 0, 0);
+        assert!(history.cpu_usage(&current, 1.0).is_empty());
+        assert!(history.io_rates(&current).is_empty());
+    }
+
+    #[test]
+    fn rates_ignore_wall_clock_steps() {
+        let start = Instant::now();
+        let mut history = History::default();
+        history.add(sample(start, 0, 0, 0));
+        let mut current = sample(start, 10, 5000, 10_000);
+        // the clock was stepped an hour back
+        current.timestamp_ms -= 3_600_000;
+        history.add(current.clone());
+
+        assert_eq!(
+            percents(&history.cpu_usage(&current, 2.0)),
+            [(1, 50.0, 25.0), (10, 50.0, 25.0)]
+        );
+        let rates = history.io_rates(&current);
+        assert_eq!(
+            rates.iter().map(|r| r.read_bytes).collect::<Vec<_>>(),
+            [1000.0, 1000.0]
+        );
+    }
+
+    #[test]
+    fn past_is_the_latest_sample_before_the_window() {
+        let start = Instant::now();
+        let mut history = History::default();
+        for secs in [0, 4, 5, 9] {
+            history.add(sample(start, secs, 0, 0));
+        }
+        let current = sample(start, 10, 0, 0);
+        let past = |secs| {
+            history
+                .past(&current, Duration::from_secs(secs))
+                .map(|s| (s.instant - start).as_secs())
+        };
+        assert_eq!(past(1), Some(9));
+        assert_eq!(past(5), Some(5));
+        assert_eq!(past(6), Some(4));
+        assert_eq!(past(10), Some(0));
+        assert_eq!(past(11), None);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use serde::Serialize;
+
+use super::StatsData;
+use crate::Error;
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Thread {
+    pub tid: i32,
+    /// Thread name, truncated to 15 bytes by the kernel.
+    pub name: String,
+    /// Scheduler state, e.g. `R` running, `S` sleeping, `D` waiting for I/O.
+    pub state: char,
+    pub user_time_us: u64,
+    pub system_time_us: u64,
+    /// User and system CPU time between the two latest samples, in percent of a core.
+    /// `None` for threads started since the previous sample.
+    pub cpu_percent: Option<f64>,
+    pub voluntary_switches: Option<u64>,
+    pub involuntary_switches: Option<u64>,
+    /// CPU the thread last ran on.
+    pub last_cpu: Option<i32>,
+}
+
+/// User and system CPU time of every thread at a point in time.
+#[derive(Debug)]
+pub(crate) struct ThreadTimes {
+    pub(crate) instant: Instant,
+    pub(crate) cpu_time_us: HashMap<i32, u64>,
+}
+
+impl ThreadTimes {
+    /// CPU usage of the threads in percent between `previous` and `self`.
+    pub(crate) fn usage_since(&self, previous: &ThreadTimes) -> HashMap<i32, f64> {
+        let elapsed_us = self.instant.duration_since(previous.instant).as_micros() as u64;
+        if elapsed_us == 0 {
+            return HashMap::new();
+        }
+        self.cpu_time_us
+            .iter()
+            .filter_map(|(tid, time)| {
+                let used_us = time.saturating_sub(*previous.cpu_time_us.get(tid)?);
+                Some((*tid, used_us as f64 * 100.0 / elapsed_us as f64))
+            })
+            .collect()
+    }
+}
+
+/// Lists threads of the current process, `usage` is the CPU usage by thread id.
+#[cfg(target_os = "linux")]
+pub fn threads(data: &StatsData, usage: &HashMap<i32, f64>) -> Result<Vec<Thread>, Error> {
+    let process = procfs::process::Process::myself()?;
+    let mut threads = Vec::new();
+    // threads can exit while they are listed
+    for task in process.tasks()?.flatten() {
+        let Ok(stat) = task.stat() else {
+            continue;
+        };
+        let status = task.status().ok();
+        threads.push(Thread {
+            tid: task.tid,
+            name: stat.comm,
+            state: stat.state,
+            user_time_us: (stat.utime as f64 * data.usage_time_to_us) as u64,
+            system_time_us: (stat.stime as f64 * data.usage_time_to_us) as u64,
+            cpu_percent: usage.get(&task.tid).copied(),
+            voluntary_switches: status.as_ref().and_then(|s| s.voluntary_ctxt_switches),
+            involuntary_switches: status.as_ref().and_then(|s| s.nonvoluntary_ctxt_switches),
+            last_cpu: stat.processor,
+        });
+    }
+    Ok(threads)
+}
+
+/// Lists threads of the current process, `usage` is the CPU usage by thread id.
+#[cfg(target_os = "macos")]
+pub fn threads(_data: &StatsData, _usage: &HashMap<i32, f64>) -> Result<Vec<Thread>, Error> {
+    Err(Error::Process(
+        "threads are listed on Linux only".to_string(),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn thread_times(data: &StatsData) -> Result<ThreadTimes, Error> {
+    let instant = Instant::now();
+    let process = procfs::process::Process::myself()?;
+    let cpu_time_us = process
+        .tasks()?
+        .flatten()
+        .filter_map(|task| {
+            let stat = task.stat().ok()?;
+            let time_us = ((stat.utime + stat.stime) as f64 * data.usage_time_to_us) as u64;
+            Some((task.tid, time_us))
+        })
+        .collect();
+    Ok(ThreadTimes {
+        instant,
+        cpu_time_us,
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn thread_times(_data: &StatsData) -> Result<ThreadTimes, Error> {
+    Err(Error::Process(
+        "threads are listed on Linux only".to_string(),
+    ))
+}
@@ -0,0 +1,342 @@
+use std::sync::Mutex;
+
+use futures_util::{Stream, StreamExt, stream};
+use regex::Regex;
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use super::{Buffer, Level, LogRecord};
+use crate::Error;
+
+/// Query of the `/groundwork/logs` endpoint, all conditions must match.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilter {
+    /// Minimal severity, records without a level are skipped when set.
+    pub level: Option<Level>,
+    /// Prefix of the record target.
+    pub target: Option<String>,
+    /// Substring of the message or a field value.
+    pub search: Option<String>,
+    /// Regular expression matched against the message or a field value.
+    pub regex: Option<String>,
+    pub from_ms: Option<u64>,
+    pub to_ms: Option<u64>,
+    /// Id of the call which emitted the record.
+    pub request_id: Option<String>,
+    /// Cursor, only records with a greater id are returned.
+    pub after: Option<u64>,
+    /// Maximal number of the newest records to return.
+    pub limit: Option<usize>,
+}
+
+impl LogFilter {
+    /// Matching records in the original order.
+    pub fn apply<'a>(
+        &self,
+        records: impl DoubleEndedIterator<Item = &'a LogRecord>,
+    ) -> Result<Vec<LogRecord>, Error> {
+        let regex = self.regex()?;
+        let mut result = records
+            .rev()
+            .take_while(|r| self.after.is_none_or(|after| r.id > after))
+            .filter(|r| self.matches(r, regex.as_ref()))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect::<Vec<_>>();
+        result.reverse();
+        Ok(result)
+    }
+
+    /// Live stream of matching records, the `after` cursor replays stored records first
+    /// and `limit` is ignored.
+    pub fn stream(
+        &self,
+        buffer: &Mutex<Buffer>,
+    ) -> Result<impl Stream<Item = LogRecord> + Send + 'static, Error> {
+        let regex = self.regex()?;
+        let (backlog, receiver) = {
+            let buffer = buffer.lock().expect("can lock buffer mutex");
+            let backlog = match self.after {
+                Some(_) => Self {
+                    limit: None,
+                    ..self.clone()
+                }
+                .apply(buffer.records())?,
+                None => vec![],
+            };
+            (backlog, buffer.subscribe())
+        };
+        let filter = self.clone();
+        let live = stream::unfold(receiver, move |mut receiver| {
+            let filter = filter.clone();
+            let regex = regex.clone();
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(r) if filter.matches(&r, regex.as_ref()) => return Some((r, receiver)),
+                        Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+        Ok(stream::iter(backlog).chain(live))
+    }
+
+    fn regex(&self) -> Result<Option<Regex>, Error> {
+        self.regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| Error::InvalidFilter(e.to_string()))
+    }
+
+    fn matches(&self, record: &LogRecord, regex: Option<&Regex>) -> bool {
+        let text_matches =
+            |f: &dyn Fn(&str) -> bool| f(&record.message) || record.fields.values().any(|v| f(v));
+        self.level
+            .is_none_or(|level| record.level.is_some_and(|l| l <= level))
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|t| record.target.starts_with(t.as_str()))
+            && self
+                .from_ms
+                .is_none_or(|ms| record.timestamp_us >= ms.saturating_mul(1000))
+            && self
+                .to_ms
+                .is_none_or(|ms| record.timestamp_us < ms.saturating_mul(1000))
+            && self
+                .request_id
+                .as_ref()
+                .is_none_or(|id| record.request_id() == Some(id.as_str()))
+            && self
+                .search
+                .as_ref()
+                .is_none_or(|s| text_matches(&|v| v.contains(s.as_str())))
+            && regex.is_none_or(|r| text_matches(&|v| r.is_match(v)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::REQUEST_ID_FIELD;
+    use futures_util::FutureExt;
+
+    const SECOND_US: u64 = 1_000_000;
+
+    fn record(
+        timestamp_s: u64,
+        level: Option<Level>,
+        target: &str,
+        message: &str,
+        fields: &[(&str, &str)],
+    ) -> LogRecord {
+        LogRecord {
+            timestamp_us: timestamp_s * SECOND_US,
+            level,
+            target: target.to_string(),
+            message: message.to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Records with ids 0 to 5, one per second.
+    fn buffer() -> Buffer {
+        let mut buffer = Buffer::default();
+        for r in [
+            record(1, Some(Level::Info), "app::http", "started", &[]),
+            record(
+                2,
+                Some(Level::Debug),
+                "app::db",
+                "query",
+                &[("table", "users")],
+            ),
+            record(
+                3,
+                Some(Level::Warn),
+                "app::http",
+                "slow request",
+                &[(REQUEST_ID_FIELD, "r1")],
+            ),
+            record(4, None, "", "plain text line", &[]),
+            record(5, Some(Level::Error), "hyper", "connection reset", &[]),
+            record(
+                6,
+                Some(Level::Info),
+                "app::db",
+                "pool size 10",
+                &[(REQUEST_ID_FIELD, "r2")],
+            ),
+        ] {
+            buffer.push(r);
+        }
+        buffer
+    }
+
+    fn ids(filter: LogFilter) -> Vec<u64> {
+        filter
+            .apply(buffer().records())
+            .unwrap()
+            .iter()
+            .map(|r| r.id)
+            .collect()
+    }
+
+    #[test]
+    fn level_and_target() {
+        assert_eq!(ids(LogFilter::default()), [0, 1, 2, 3, 4, 5]);
+        let level = |level| LogFilter {
+            level: Some(level),
+            ..Default::default()
+        };
+        // records without a level are skipped
+        assert_eq!(ids(level(Level::Trace)), [0, 1, 2, 4, 5]);
+        assert_eq!(ids(level(Level::Info)), [0, 2, 4, 5]);
+        assert_eq!(ids(level(Level::Error)), [4]);
+        assert_eq!(
+            ids(LogFilter {
+                target: Some("app".to_string()),
+                ..level(Level::Info)
+            }),
+            [0, 2, 5]
+        );
+        assert_eq!(
+            ids(LogFilter {
+                target: Some("app::db".to_string()),
+                ..Default::default()
+            }),
+            [1, 5]
+        );
+    }
+
+    #[test]
+    fn text_and_regex() {
+        let search = |s: &str| LogFilter {
+            search: Some(s.to_string()),
+            ..Default::default()
+        };
+        let regex = |s: &str| LogFilter {
+            regex: Some(s.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(search("re")), [2, 4]);
+        // field values are searched too
+        assert_eq!(ids(search("users")), [1]);
+        assert_eq!(ids(search("Slow")), Vec::<u64>::new());
+        assert_eq!(ids(regex(r"(?i)^slow|size \d+")), [2, 5]);
+        assert_eq!(ids(regex("^r[12]$")), [2, 5]);
+        assert_eq!(
+            ids(LogFilter {
+                level: Some(Level::Error),
+                ..search("re")
+            }),
+            [4]
+        );
+        assert!(matches!(
+            regex("(").apply(buffer().records()),
+            Err(Error::InvalidFilter(_))
+        ));
+    }
+
+    #[test]
+    fn time_and_request_id() {
+        let between = |from_ms, to_ms| LogFilter {
+            from_ms,
+            to_ms,
+            ..Default::default()
+        };
+        assert_eq!(ids(between(Some(2000), Some(4000))), [1, 2]);
+        assert_eq!(ids(between(Some(5500), None)), [5]);
+        assert_eq!(ids(between(None, Some(1000))), Vec::<u64>::new());
+        // the milliseconds saturate instead of overflowing
+        assert_eq!(ids(between(Some(u64::MAX), None)), Vec::<u64>::new());
+        assert_eq!(ids(between(None, Some(u64::MAX))), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(
+            ids(LogFilter {
+                request_id: Some("r2".to_string()),
+                ..Default::default()
+            }),
+            [5]
+        );
+    }
+
+    #[test]
+    fn cursor_and_limit() {
+        let page = |after, limit| LogFilter {
+            after,
+            limit,
+            ..Default::default()
+        };
+        assert_eq!(ids(page(Some(3), None)), [4, 5]);
+        assert_eq!(ids(page(Some(5), None)), Vec::<u64>::new());
+        // the newest records are kept
+        assert_eq!(ids(page(None, Some(2))), [4, 5]);
+        assert_eq!(ids(page(Some(1), Some(3))), [3, 4, 5]);
+        assert_eq!(ids(page(None, Some(0))), Vec::<u64>::new());
+        assert_eq!(
+            ids(LogFilter {
+                level: Some(Level::Info),
+                ..page(Some(0), Some(2))
+            }),
+            [4, 5]
+        );
+        assert_eq!(
+            ids(LogFilter {
+                target: Some("app::http".to_string()),
+                ..page(None, Some(5))
+            }),
+            [0, 2]
+        );
+    }
+
+    #[test]
+    fn stream_sends_matching_records_pushed_after_subscribing() {
+        let buffer = Mutex::new(buffer());
+        let filter = LogFilter {
+            level: Some(Level::Warn),
+            ..Default::default()
+        };
+        let mut stream = Box::pin(filter.stream(&buffer).unwrap());
+        // stored records are not replayed without a cursor
+        assert!(stream.next().now_or_never().is_none());
+
+        let push = |r| buffer.lock().unwrap().push(r);
+        push(record(7, Some(Level::Info), "app", "filtered out", &[]));
+        assert!(stream.next().now_or_never().is_none());
+        push(record(8, Some(Level::Warn), "app", "disk almost full", &[]));
+        let received = stream.next().now_or_never().flatten().unwrap();
+        assert_eq!(
+            (received.id, received.message.as_str()),
+            (7, "disk almost full")
+        );
+        assert!(stream.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn stream_replays_records_after_the_cursor() {
+        let buffer = Mutex::new(buffer());
+        let filter = LogFilter {
+            after: Some(2),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let mut stream = Box::pin(filter.stream(&buffer).unwrap());
+        buffer
+            .lock()
+            .unwrap()
+            .push(record(7, None, "", "live", &[]));
+        let ids: Vec<_> = (0..4)
+            .map(|_| stream.next().now_or_never().flatten().unwrap().id)
+            .collect();
+        assert_eq!(ids, [3, 4, 5, 6]);
+        assert!(stream.next().now_or_never().is_none());
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+use super::{Buffer, LogRecord, LogSource, LogSpan, timestamp_us};
+
+/// [`tracing_subscriber::Layer`] which stores every event as a structured [`LogRecord`].
+pub struct LogLayer {
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+impl LogLayer {
+    pub fn new(buffer: Arc<Mutex<Buffer>>) -> Self {
+        Self { buffer }
+    }
+}
+
+/// Fields added by `tracing_log` to forwarded records, they are restored by normalization.
+const LOG_FIELDS: [&str; 4] = ["log.target", "log.module_path", "log.file", "log.line"];
+
+/// Span fields, kept in the span extensions.
+struct SpanFields(BTreeMap<String, String>);
+
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: BTreeMap<String, String>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.record(field, format!("{value:?}"));
+    }
+}
+
+impl FieldVisitor {
+    fn record(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = Some(value);
+        } else if !LOG_FIELDS.contains(&field.name()) {
+            self.fields.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for LogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanFields(visitor.fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut visitor = FieldVisitor::default();
+        values.record(&mut visitor);
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanFields>() {
+            Some(fields) => fields.0.append(&mut visitor.fields),
+            None => extensions.insert(SpanFields(visitor.fields)),
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .map(|span| LogSpan {
+                        name: span.name().to_string(),
+                        fields: span
+                            .extensions()
+                            .get::<SpanFields>()
+                            .map(|f| f.0.clone())
+                            .unwrap_or_default(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // records of the log crate forwarded by `tracing_log::LogTracer`
+        let normalized = event.normalized_metadata();
+        let (source, metadata) = match &normalized {
+            Some(metadata) => (LogSource::Log, metadata),
+            None => (LogSource::Tracing, event.metadata()),
+        };
+        let record = LogRecord {
+            source,
+            timestamp_us: timestamp_us(),
+            level: Some(metadata.level().into()),
+            target: metadata.target().to_string(),
+            module: metadata.module_path().map(str::to_string),
+            file: metadata.file().map(str::to_string),
+            line: metadata.line(),
+            message: visitor.message.unwrap_or_default(),
+            fields: visitor.fields,
+            spans,
+            ..Default::default()
+        };
+        self.buffer
+            .lock()
+            .expect("can lock buffer mutex")
+            .push(record);
+    }
+}
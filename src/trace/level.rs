@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+use tracing::Subscriber;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{EnvFilter, reload};
+
+use crate::Error;
+
+/// Body of the `/groundwork/log-level` endpoint, directives use the `RUST_LOG` syntax.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogLevel {
+    pub directives: String,
+}
+
+type Reload = Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>;
+
+/// Filters controlled by the directives, both can be installed.
+#[derive(Default)]
+struct Filters {
+    directives: String,
+    /// Reloads the filter created by [`LevelControl::layer`].
+    reload: Option<Reload>,
+    /// Filter of the [`super::SpyLogger`] records.
+    log: Option<Arc<RwLock<LogDirectives>>>,
+}
+
+/// Runtime control of the filters installed by [`LevelControl::layer`] and
+/// [`LevelControl::log_filter`].
+#[derive(Clone, Default)]
+pub(crate) struct LevelControl(Arc<Mutex<Filters>>);
+
+impl LevelControl {
+    /// Creates the reloadable filter layer, it should wrap the root subscriber.
+    pub(crate) fn layer<S>(&self, directives: &str) -> Result<reload::Layer<EnvFilter, S>, Error>
+    where
+        S: Subscriber + 'static,
+    {
+        let filter = parse(directives)?;
+        set_log_max_level(filter.max_level_hint());
+        let (layer, handle) = reload::Layer::new(filter);
+        let mut filters = self.0.lock().expect("can lock level mutex");
+        filters.reload = Some(Box::new(move |filter| handle.reload(filter)));
+        filters.set_directives(directives);
+        Ok(layer)
+    }
+
+    /// Filter of the `log` records captured by [`super::SpyLogger`], shared by all of them.
+    /// `directives` are used unless the directives are already set.
+    pub(crate) fn log_filter(&self, directives: &str) -> Arc<RwLock<LogDirectives>> {
+        let mut filters = self.0.lock().expect("can lock level mutex");
+        if filters.log.is_none() {
+            if !filters.is_configured() {
+                filters.directives = directives.to_string();
+            }
+            filters.log = Some(Arc::new(RwLock::new(LogDirectives::parse(
+                &filters.directives,
+            ))));
+        }
+        filters.log.clone().expect("log filter is set")
+    }
+
+    /// Sets the `log` crate max level from the directives, the records are filtered
+    /// before they reach the logger.
+    pub(crate) fn apply_log_max_level(&self) {
+        let filters = self.0.lock().expect("can lock level mutex");
+        if let Ok(filter) = parse(&filters.directives) {
+            set_log_max_level(filter.max_level_hint());
+        }
+    }
+
+    pub(crate) fn get(&self) -> Result<LogLevel, Error> {
+        let filters = self.0.lock().expect("can lock level mutex");
+        if !filters.is_configured() {
+            return Err(Error::NotConfigured("reloadable log filter"));
+        }
+        Ok(LogLevel {
+            directives: filters.directives.clone(),
+        })
+    }
+
+    pub(crate) fn set(&self, directives: &str) -> Result<LogLevel, Error> {
+        let filter = parse(directives)?;
+        let mut filters = self.0.lock().expect("can lock level mutex");
+        if !filters.is_configured() {
+            return Err(Error::NotConfigured("reloadable log filter"));
+        }
+        let max_level = filter.max_level_hint();
+        if let Some(reload) = &filters.reload {
+            // fails only when the subscriber is dropped
+            reload(filter).map_err(|_| Error::NotConfigured("reloadable log filter"))?;
+        }
+        set_log_max_level(max_level);
+        filters.set_directives(directives);
+        Ok(LogLevel {
+            directives: filters.directives.clone(),
+        })
+    }
+}
+
+impl Filters {
+    fn is_configured(&self) -> bool {
+        self.reload.is_some() || self.log.is_some()
+    }
+
+    fn set_directives(&mut self, directives: &str) {
+        self.directives = directives.to_string();
+        if let Some(log) = &self.log {
+            *log.write().expect("can lock log filter") = LogDirectives::parse(directives);
+        }
+    }
+}
+
+/// Target and level directives of the `RUST_LOG` syntax applied to `log` records, span and
+/// field directives don't apply to them and are skipped.
+#[derive(Debug, Default)]
+pub(crate) struct LogDirectives {
+    /// The longest target first, `None` matches every target.
+    directives: Vec<(Option<String>, log::LevelFilter)>,
+}
+
+impl LogDirectives {
+    /// Parses directives already validated by [`parse`].
+    fn parse(directives: &str) -> Self {
+        let mut result = directives
+            .split(',')
+            .map(str::trim)
+            .filter(|d| !d.is_empty() && !d.contains('['))
+            .filter_map(|d| match d.split_once('=') {
+                Some((target, level)) => Some((Some(target.to_string()), level.parse().ok()?)),
+                // a bare target enables all its levels
+                None => Some(match d.parse() {
+                    Ok(level) => (None, level),
+                    Err(_) => (Some(d.to_string()), log::LevelFilter::Trace),
+                }),
+            })
+            .collect::<Vec<_>>();
+        result.sort_by_key(|(target, _)| std::cmp::Reverse(target.as_ref().map_or(0, String::len)));
+        Self { directives: result }
+    }
+
+    /// Records without a matching directive are disabled, as in `EnvFilter`.
+    pub(crate) fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.directives
+            .iter()
+            .find(|(target, _)| {
+                target
+                    .as_ref()
+                    .is_none_or(|t| metadata.target().starts_with(t.as_str()))
+            })
+            .is_some_and(|(_, level)| metadata.level() <= *level)
+    }
+}
+
+fn parse(directives: &str) -> Result<EnvFilter, Error> {
+    EnvFilter::try_new(directives).map_err(|e| Error::InvalidFilter(e.to_string()))
+}
+
+/// Records of the `log` crate are filtered by its global max level.
+fn set_log_max_level(hint: Option<LevelFilter>) {
+    log::set_max_level(match hint {
+        Some(LevelFilter::OFF) => log::LevelFilter::Off,
+        Some(LevelFilter::ERROR) => log::LevelFilter::Error,
+        Some(LevelFilter::WARN) => log::LevelFilter::Warn,
+        Some(LevelFilter::INFO) => log::LevelFilter::Info,
+        Some(LevelFilter::DEBUG) => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    });
+}
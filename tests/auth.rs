@@ -0,0 +1,61 @@
+#![cfg(any(feature = "poem", feature = "axum"))]
+
+use groundwork::Groundwork;
+
+#[cfg(feature = "poem")]
+fn status(app: &impl poem::Endpoint, uri: &str) -> u16 {
+    let request = poem::Request::builder().uri_str(uri).finish();
+    runtime()
+        .block_on(app.get_response(request))
+        .status()
+        .as_u16()
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    // the log stream sends keep alive events with a tokio timer
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+#[cfg(feature = "poem")]
+#[test]
+fn poem_authorization_query_parameter_is_accepted_by_the_log_stream_only() {
+    let groundwork = Groundwork::builder("test").bearer_token("t0ken").build();
+    let app = groundwork.register_handlers(poem::Route::new(), "/status");
+    let query = "authorization=Bearer%20t0ken";
+
+    assert_eq!(
+        status(&app, &format!("/groundwork/logs/stream?{query}")),
+        200
+    );
+    assert_eq!(status(&app, "/groundwork/logs/stream"), 401);
+    assert_eq!(status(&app, &format!("/groundwork/logs?{query}")), 401);
+    assert_eq!(status(&app, &format!("/groundwork/stats?{query}")), 401);
+    assert_eq!(status(&app, "/status"), 200);
+}
+
+#[cfg(feature = "axum")]
+#[test]
+fn axum_authorization_query_parameter_is_accepted_by_the_log_stream_only() {
+    use tower_service::Service;
+
+    let groundwork = Groundwork::builder("test").bearer_token("t0ken").build();
+    let mut app = groundwork.register_axum_handlers(axum::Router::new(), "/status");
+    let mut status = |uri: &str| {
+        let request = http::Request::builder()
+            .uri(uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = runtime().block_on(app.call(request)).unwrap();
+        response.status().as_u16()
+    };
+    let query = "authorization=Bearer%20t0ken";
+
+    assert_eq!(status(&format!("/groundwork/logs/stream?{query}")), 200);
+    assert_eq!(status("/groundwork/logs/stream"), 401);
+    assert_eq!(status(&format!("/groundwork/logs?{query}")), 401);
+    assert_eq!(status(&format!("/groundwork/stats?{query}")), 401);
+    assert_eq!(status("/status"), 200);
+}
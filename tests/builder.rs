@@ -0,0 +1,81 @@
+#![cfg(feature = "poem")]
+
+use futures_util::FutureExt;
+use groundwork::Groundwork;
+use groundwork::trace::LogFilter;
+use poem::{Endpoint, EndpointExt, Request, Route, get, handler};
+use serde_json::Value;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[handler]
+fn hello() -> &'static str {
+    "hello"
+}
+
+/// Calls the endpoint and reads the whole response body, the call is recorded once it is read.
+fn get_body(app: &impl Endpoint, uri: &str, request_id: &str) -> String {
+    let request = Request::builder()
+        .uri_str(uri)
+        .header("x-request-id", request_id)
+        .finish();
+    let response = app.get_response(request).now_or_never().unwrap();
+    assert!(
+        response.status().is_success(),
+        "{uri}: {}",
+        response.status()
+    );
+    response
+        .into_body()
+        .into_string()
+        .now_or_never()
+        .unwrap()
+        .unwrap()
+}
+
+fn get_json(app: &impl Endpoint, uri: &str) -> Vec<Value> {
+    serde_json::from_str(&get_body(app, uri, "status")).unwrap()
+}
+
+#[test]
+fn small_log_buffer_keeps_newest_records() {
+    let groundwork = Groundwork::builder("test").log_buffer_bytes(4096).build();
+    let subscriber = tracing_subscriber::registry().with(groundwork.tracing_layer());
+    tracing::subscriber::with_default(subscriber, || {
+        for i in 0..100 {
+            tracing::warn!("record {i}");
+        }
+    });
+
+    let logs = groundwork.logs(&LogFilter::default()).unwrap();
+    assert!(
+        !logs.is_empty() && logs.len() < 100,
+        "{} records",
+        logs.len()
+    );
+    assert_eq!(logs.last().unwrap().message, "record 99");
+    assert_eq!(logs[0].message, format!("record {}", 100 - logs.len()));
+
+    let app = groundwork.register_handlers(Route::new(), "/status");
+    let served = get_json(&app, "/groundwork/logs");
+    assert_eq!(served.len(), logs.len());
+    assert_eq!(served.last().unwrap()["message"], "record 99");
+}
+
+#[test]
+fn small_call_buffer_keeps_newest_calls() {
+    let groundwork = Groundwork::builder("test").call_buffer_len(3).build();
+    let app = groundwork
+        .register_handlers(Route::new(), "/status")
+        .at("/hello", get(hello).with(groundwork.calls_middleware()));
+    for i in 0..5 {
+        assert_eq!(get_body(&app, "/hello", &format!("hello-{i}")), "hello");
+    }
+
+    let calls = groundwork.calls();
+    let ids: Vec<_> = calls.iter().map(|c| c.request_id.as_str()).collect();
+    assert_eq!(ids, ["hello-2", "hello-3", "hello-4"]);
+
+    let served = get_json(&app, "/groundwork/calls");
+    let ids: Vec<_> = served.iter().map(|c| c["requestId"].as_str()).collect();
+    assert_eq!(ids, [Some("hello-2"), Some("hello-3"), Some("hello-4")]);
+}
@@ -0,0 +1,198 @@
+#![cfg(any(feature = "poem", feature = "axum"))]
+
+use futures_util::FutureExt;
+use groundwork::Groundwork;
+use groundwork::call::{CallMiddleware, CallOutcome};
+use groundwork::trace::LogFilter;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[cfg(feature = "poem")]
+#[test]
+fn poem_handler_dropped_before_response_is_client_aborted() {
+    use poem::{Endpoint, EndpointExt, Request, Route, get, handler};
+
+    #[handler]
+    async fn slow() -> &'static str {
+        std::future::pending().await
+    }
+
+    let calls = CallMiddleware::new();
+    let app = Route::new().at("/slow", get(slow)).with(calls.clone());
+
+    // the client disconnects while the handler is running
+    let response = app
+        .call(Request::builder().uri_str("/slow").finish())
+        .now_or_never();
+    assert!(response.is_none());
+
+    assert!(calls.inflight().is_empty());
+    let recorded = calls.calls();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].outcome, CallOutcome::ClientAborted);
+}
+
+#[cfg(feature = "axum")]
+#[test]
+fn axum_handler_dropped_before_response_is_client_aborted() {
+    use axum::{Router, body::Body, routing::get};
+    use tower_service::Service;
+
+    let calls = CallMiddleware::new();
+    let mut app = Router::new()
+        .route("/slow", get(std::future::pending::<&'static str>))
+        .layer(calls.clone());
+
+    let request = http::Request::builder()
+        .uri("/slow")
+        .body(Body::empty())
+        .unwrap();
+    assert!(app.call(request).now_or_never().is_none());
+
+    assert!(calls.inflight().is_empty());
+    let recorded = calls.calls();
+    assert_eq!(recorded.len(), 1);
+    assert_eq!(recorded[0].route, "/slow");
+    assert_eq!(recorded[0].outcome, CallOutcome::ClientAborted);
+}
+
+#[cfg(feature = "poem")]
+#[test]
+fn poem_records_keep_request_id_when_call_span_is_disabled() {
+    use poem::{Endpoint, EndpointExt, Request, Route, get, handler};
+
+    #[handler]
+    fn warn() -> &'static str {
+        tracing::warn!("cache is cold");
+        "ok"
+    }
+
+    let groundwork = Groundwork::new("test");
+    // the `call` span is at info level
+    let subscriber = tracing_subscriber::registry()
+        .with(groundwork.log_filter_layer("warn").unwrap())
+        .with(groundwork.tracing_layer());
+    let app = Route::new()
+        .at("/warn", get(warn))
+        .with(groundwork.calls_middleware());
+    let request = Request::builder()
+        .uri_str("/warn")
+        .header("x-request-id", "cold-cache")
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        let response = app.call(request).now_or_never().unwrap().unwrap();
+        assert!(response.status().is_success());
+    });
+
+    let filter = LogFilter {
+        request_id: Some("cold-cache".to_string()),
+        ..Default::default()
+    };
+    let logs = groundwork.logs(&filter).unwrap();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].message, "cache is cold");
+}
+
+#[cfg(feature = "axum")]
+#[test]
+fn axum_records_keep_request_id_when_call_span_is_disabled() {
+    use axum::{Router, body::Body, routing::get};
+    use tower_service::Service;
+
+    let groundwork = Groundwork::new("test");
+    let subscriber = tracing_subscriber::registry()
+        .with(groundwork.log_filter_layer("warn").unwrap())
+        .with(groundwork.tracing_layer());
+    let mut app = Router::new()
+        .route(
+            "/warn",
+            get(|| async {
+                tracing::warn!("cache is cold");
+                "ok"
+            }),
+        )
+        .layer(groundwork.calls_middleware());
+    let request = http::Request::builder()
+        .uri("/warn")
+        .header("x-request-id", "cold-cache")
+        .body(Body::empty())
+        .unwrap();
+    tracing::subscriber::with_default(subscriber, || {
+        let response = app.call(request).now_or_never().unwrap().unwrap();
+        assert!(response.status().is_success());
+    });
+
+    let filter = LogFilter {
+        request_id: Some("cold-cache".to_string()),
+        ..Default::default()
+    };
+    let logs = groundwork.logs(&filter).unwrap();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].message, "cache is cold");
+}
+
+#[cfg(feature = "poem")]
+#[test]
+fn poem_unknown_paths_share_one_route() {
+    use poem::{Endpoint, EndpointExt, Request, Route, get, handler};
+
+    #[handler]
+    fn hello() -> &'static str {
+        "hello"
+    }
+
+    let calls = CallMiddleware::new();
+    let app = Route::new().at("/hello", get(hello)).with(calls.clone());
+    for uri in ["/hello", "/missing/1", "/missing/2", "/.env"] {
+        let request = Request::builder().uri_str(uri).finish();
+        _ = app.get_response(request).now_or_never().unwrap();
+    }
+
+    let routes: Vec<_> = calls.calls().into_iter().map(|c| c.route).collect();
+    assert_eq!(
+        routes,
+        ["/hello", "<unmatched>", "<unmatched>", "<unmatched>"]
+    );
+    let totals: Vec<_> = calls
+        .totals()
+        .into_iter()
+        .map(|t| (t.route, t.count))
+        .collect();
+    assert_eq!(
+        totals,
+        [("/hello".to_string(), 1), ("<unmatched>".to_string(), 3)]
+    );
+}
+
+#[cfg(feature = "axum")]
+#[test]
+fn axum_unknown_paths_share_one_route() {
+    use axum::{Router, body::Body, routing::get};
+    use tower_service::Service;
+
+    let calls = CallMiddleware::new();
+    let mut app = Router::new()
+        .route("/hello", get(|| async { "hello" }))
+        .layer(calls.clone());
+    for uri in ["/hello", "/missing/1", "/missing/2", "/.env"] {
+        let request = http::Request::builder()
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        _ = app.call(request).now_or_never().unwrap();
+    }
+
+    let routes: Vec<_> = calls.calls().into_iter().map(|c| c.route).collect();
+    assert_eq!(
+        routes,
+        ["/hello", "<unmatched>", "<unmatched>", "<unmatched>"]
+    );
+    let totals: Vec<_> = calls
+        .totals()
+        .into_iter()
+        .map(|t| (t.route, t.count))
+        .collect();
+    assert_eq!(
+        totals,
+        [("/hello".to_string(), 1), ("<unmatched>".to_string(), 3)]
+    );
+}
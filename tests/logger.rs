@@ -0,0 +1,46 @@
+use groundwork::Groundwork;
+use groundwork::trace::LogFilter;
+
+/// Logger of the service wrapped by the spy logger.
+struct Discard;
+
+impl log::Log for Discard {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, _: &log::Record) {}
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn register_logger_captures_and_filters_log_records() {
+    let groundwork = Groundwork::new("test");
+    groundwork.register_logger(Discard).unwrap();
+
+    log::warn!("disk is almost full");
+
+    let logs = groundwork.logs(&LogFilter::default()).unwrap();
+    assert!(logs.iter().any(|r| r.message == "disk is almost full"));
+
+    // the level can be changed at runtime without a tracing subscriber
+    groundwork.set_log_level("error,logger=info").unwrap();
+    assert_eq!(
+        groundwork.log_level().unwrap().directives,
+        "error,logger=info"
+    );
+    log::debug!("skipped debug record");
+    log::info!("kept info record");
+    log::warn!(target: "other", "skipped warn record");
+
+    let messages = groundwork
+        .logs(&LogFilter::default())
+        .unwrap()
+        .into_iter()
+        .map(|r| r.message)
+        .collect::<Vec<_>>();
+    assert!(messages.contains(&"kept info record".to_string()));
+    assert!(!messages.contains(&"skipped debug record".to_string()));
+    assert!(!messages.contains(&"skipped warn record".to_string()));
+}
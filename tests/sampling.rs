@@ -0,0 +1,29 @@
+#![cfg(target_os = "linux")]
+
+use groundwork::Groundwork;
+
+fn sampling_threads() -> usize {
+    std::fs::read_dir("/proc/self/task")
+        .unwrap()
+        .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+        // `groundwork-stats` truncated to 15 bytes by the kernel
+        .filter(|name| name.trim() == "groundwork-stat")
+        .count()
+}
+
+#[test]
+fn sampling_starts_once_on_request() {
+    let groundwork = Groundwork::new("test");
+    assert_eq!(sampling_threads(), 0);
+
+    groundwork.start_sampling();
+    groundwork.clone().start_sampling();
+    // the thread sets its name once it runs
+    for _ in 0..100 {
+        if sampling_threads() > 0 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(sampling_threads(), 1);
+}